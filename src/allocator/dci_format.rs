@@ -0,0 +1,227 @@
+use crate::scheduler_config::{CellConfig, Duplex};
+
+use super::link_adaptation::PdcchLinkModel;
+use super::structures::PdcchAggregation;
+
+/// Payload sizes which UE can't tell apart, padded with one zero bit (36.212 table 5.3.3.1.2-1)
+const AMBIGUOUS_SIZES: [u16; 10] = [12, 14, 16, 20, 24, 26, 32, 40, 44, 56];
+/// MCS, NDI and RV of one transport block
+const TRANSPORT_BLOCK_BITS: u16 = 5 + 1 + 2;
+
+/// DCI formats (36.212 5.3.3.1)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[repr(u8)]
+pub enum DciFormat {
+    /// PUSCH grant
+    F0,
+    /// PDSCH assignment with one codeword, resource allocation type 0 or 1
+    F1,
+    /// Compact PDSCH assignment, fallback of every transmission mode
+    #[default]
+    F1A,
+    /// Very compact PDSCH assignment for paging, random access response and system information
+    F1C,
+    /// Closed-loop spatial multiplexing
+    F2,
+    /// Open-loop spatial multiplexing
+    F2A,
+    /// TPC commands of 2 bits
+    F3,
+    /// TPC commands of 1 bit
+    F3A,
+}
+
+impl DciFormat {
+    pub const fn index(&self) -> usize {
+        *self as usize
+    }
+
+    pub const fn list() -> [DciFormat; 8] {
+        [
+            DciFormat::F0,
+            DciFormat::F1,
+            DciFormat::F1A,
+            DciFormat::F1C,
+            DciFormat::F2,
+            DciFormat::F2A,
+            DciFormat::F3,
+            DciFormat::F3A,
+        ]
+    }
+
+    /// Format of PDSCH assignments in UE-specific search space for transmission mode 1..7
+    /// (36.213 table 7.1-5). `None` for modes 5 and 6, formats 1D and 1B are not modelled
+    pub const fn for_transmission_mode(transmission_mode: u8) -> Option<DciFormat> {
+        match transmission_mode {
+            1 | 2 | 7 => Some(DciFormat::F1),
+            3 => Some(DciFormat::F2A),
+            4 => Some(DciFormat::F2),
+            _ => None,
+        }
+    }
+}
+
+/// Payload sizes of DCI formats without CRC in the cell (36.212 5.3.3.1).
+/// Uplink bandwidth is assumed equal to the downlink one
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DciSizes {
+    payload_bits: [u16; 8],
+}
+
+impl DciSizes {
+    pub fn new(cell_config: &CellConfig) -> Self {
+        let nof_prb = cell_config.nof_prb as u32;
+        let tdd = matches!(cell_config.duplex, Duplex::Tdd { .. });
+        let harq_bits = if tdd { 4 } else { 3 };
+        // Downlink assignment index, UL index for format 0 in configuration 0
+        let dai_bits = if tdd { 2 } else { 0 };
+
+        // Resource allocation type 2: RIV of contiguous PRBs
+        let compact_bits = ceil_log2(nof_prb * (nof_prb + 1) / 2);
+        // Format 0 is padded to the size of format 1A and vice versa
+        let format_0 = 1 + 1 + compact_bits + 5 + 1 + 2 + 3 + 1 + dai_bits;
+        let format_1a = 1 + 1 + compact_bits + 5 + harq_bits + 1 + 2 + 2 + dai_bits;
+        let mut format_0_1a = format_0.max(format_1a);
+        if AMBIGUOUS_SIZES.contains(&format_0_1a) {
+            format_0_1a += 1;
+        }
+
+        // Resource allocation types 0 and 1: bitmap of RBGs and the type header
+        let rbg_size = match nof_prb {
+            0..=10 => 1,
+            11..=26 => 2,
+            27..=63 => 3,
+            _ => 4,
+        };
+        let bitmap_bits = nof_prb.div_ceil(rbg_size) as u16 + (nof_prb > 10) as u16;
+
+        let mut format_1 = bitmap_bits + 5 + harq_bits + 1 + 2 + 2 + dai_bits;
+        if format_1 == format_0_1a {
+            format_1 += 1;
+        }
+        while AMBIGUOUS_SIZES.contains(&format_1) {
+            format_1 += 1;
+        }
+
+        // Precoding information (36.212 tables 5.3.3.1.5-3 and 5.3.3.1.5A-1)
+        let (precoding_2, precoding_2a) = if cell_config.nof_ports == 4 {
+            (6, 2)
+        } else {
+            (3, 0)
+        };
+        // TPC, HARQ process, transport block to codeword swap flag, two transport blocks
+        let format_2_common = bitmap_bits + 2 + harq_bits + 1 + 2 * TRANSPORT_BLOCK_BITS + dai_bits;
+        let pad = |size: u16| size + AMBIGUOUS_SIZES.contains(&size) as u16;
+
+        let mut payload_bits = [0; 8];
+        payload_bits[DciFormat::F0.index()] = format_0_1a;
+        payload_bits[DciFormat::F1.index()] = format_1;
+        payload_bits[DciFormat::F1A.index()] = format_0_1a;
+        payload_bits[DciFormat::F1C.index()] = format_1c_size(nof_prb);
+        payload_bits[DciFormat::F2.index()] = pad(format_2_common + precoding_2);
+        payload_bits[DciFormat::F2A.index()] = pad(format_2_common + precoding_2a);
+        payload_bits[DciFormat::F3.index()] = format_0_1a;
+        payload_bits[DciFormat::F3A.index()] = format_0_1a;
+        DciSizes { payload_bits }
+    }
+
+    pub fn payload_bits(&self, format: DciFormat) -> u16 {
+        self.payload_bits[format.index()]
+    }
+
+    /// Coding rate of DCI with CRC at `aggregation_level`
+    pub fn coding_rate(&self, format: DciFormat, aggregation_level: PdcchAggregation) -> f32 {
+        PdcchLinkModel::coding_rate(aggregation_level, self.payload_bits(format))
+    }
+}
+
+/// Size of format 1C: gap flag, RIV of virtual PRBs in steps of N_step and TBS index
+/// (36.212 5.3.3.1.4, 36.211 table 6.2.3.2-1)
+fn format_1c_size(nof_prb: u32) -> u16 {
+    let gap = match nof_prb {
+        0..=10 => nof_prb.div_ceil(2),
+        11 => 4,
+        12..=19 => 8,
+        20..=26 => 12,
+        27..=44 => 18,
+        45..=63 => 27,
+        64..=79 => 32,
+        _ => 48,
+    };
+    let vrb_count = 2 * gap.min(nof_prb - gap);
+    let step = if nof_prb < 50 { 2 } else { 4 };
+    let step_count = vrb_count / step;
+    let gap_bits = (nof_prb >= 50) as u16;
+    gap_bits + ceil_log2(step_count * (step_count + 1) / 2) + 5
+}
+
+fn ceil_log2(value: u32) -> u16 {
+    (u32::BITS - value.saturating_sub(1).leading_zeros()) as u16
+}
+
+/// Largest coding rate of DCI with CRC. Above it UE decodes DCI unreliably at any SINR,
+/// the allocator refuses such aggregation levels
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CodingRateLimit {
+    pub sizes: DciSizes,
+    pub max_coding_rate: f32,
+}
+
+impl CodingRateLimit {
+    pub fn allows(&self, format: DciFormat, aggregation_level: PdcchAggregation) -> bool {
+        self.sizes.coding_rate(format, aggregation_level) <= self.max_coding_rate
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::allocator::test_fixture::cell;
+    use DciFormat::*;
+
+    fn sizes(cell_config: &CellConfig) -> [u16; 8] {
+        let sizes = DciSizes::new(cell_config);
+        DciFormat::list().map(|format| sizes.payload_bits(format))
+    }
+
+    #[test]
+    fn fdd_sizes() {
+        // Formats 0, 1, 1A, 1C, 2, 2A, 3, 3A with 2 ports
+        let expected = [
+            (6, [21, 19, 21, 8, 31, 28, 21, 21]),
+            (15, [22, 23, 22, 10, 34, 31, 22, 22]),
+            (25, [25, 27, 25, 12, 39, 36, 25, 25]),
+            (50, [27, 31, 27, 13, 43, 41, 27, 27]),
+            (75, [27, 33, 27, 14, 45, 42, 27, 27]),
+            (100, [28, 39, 28, 15, 51, 48, 28, 28]),
+        ];
+        for (nof_prb, payload_bits) in expected {
+            assert_eq!(sizes(&cell(nof_prb)), payload_bits, "{} PRB", nof_prb);
+        }
+
+        let mut cell_config = cell(100);
+        cell_config.nof_ports = 4;
+        assert_eq!(sizes(&cell_config)[F2.index()], 54);
+        assert_eq!(sizes(&cell_config)[F2A.index()], 50);
+    }
+
+    #[test]
+    fn tdd_sizes() {
+        let mut cell_config = cell(100);
+        cell_config.duplex = Duplex::Tdd { ul_dl_config: 1 };
+        assert_eq!(sizes(&cell_config), [31, 42, 31, 15, 54, 51, 31, 31]);
+    }
+
+    #[test]
+    fn coding_rate_limit() {
+        let limit = CodingRateLimit {
+            sizes: DciSizes::new(&cell(100)),
+            max_coding_rate: 0.8,
+        };
+        assert!(!limit.allows(F2, PdcchAggregation::L1));
+        assert!(limit.allows(F2, PdcchAggregation::L2));
+        assert!(limit.allows(F1A, PdcchAggregation::L1));
+        assert_eq!(DciFormat::for_transmission_mode(4), Some(F2));
+        assert_eq!(DciFormat::for_transmission_mode(5), None);
+    }
+}
@@ -0,0 +1,289 @@
+use crate::resource_allocation;
+use crate::scheduler_config::CellConfig;
+use crate::types::bounded_vec::BoundedVec;
+use crate::types::cfi::Cfi;
+
+use super::structures::{AggregationChoice, CceCountTable, PdcchAggregation};
+
+/// Assumed wideband SINR for CQI 1..15 at 10% BLER of PDSCH. 36.213 table 7.2.3-1 defines
+/// only modulation and code rate of every CQI, the mapping to SINR is a typical AWGN one
+const CQI_SINR_DB: [f32; 15] = [
+    -6.7, -4.7, -2.3, 0.2, 2.4, 4.3, 5.9, 8.1, 10.3, 11.7, 14.1, 16.3, 18.7, 21.0, 22.7,
+];
+/// Coded bits in one CCE: 36 REs of QPSK
+const BITS_PER_CCE: f32 = 72.0;
+/// CRC attached to DCI payload (36.212 5.3.3.2)
+const DCI_CRC_BITS: u16 = 16;
+/// Distance of tail-biting convolutional code of short blocks from Shannon capacity
+const CODING_GAP_DB: f32 = 1.5;
+/// Slope of BLER curve: BLER falls from 50% to 1% in about 2 dB
+const BLER_SLOPE_PER_DB: f32 = 2.3;
+/// Gain of SFBC over one port for 1, 2 and 4 ports, PDCCH is sent with transmit diversity
+const TX_DIVERSITY_GAIN_DB: [f32; 3] = [0.0, 2.0, 3.0];
+/// Limits of the outer loop offset
+const MAX_OFFSET_DB: f32 = 10.0;
+/// Offset step after a missed DCI
+const OFFSET_STEP_DOWN_DB: f32 = 1.0;
+
+/// Wideband SINR for CQI 1..15, CQI 0 is out of range
+pub fn cqi_sinr_db(cqi: u8) -> f32 {
+    match cqi {
+        0 => f32::NEG_INFINITY,
+        _ => CQI_SINR_DB[(cqi as usize).min(CQI_SINR_DB.len()) - 1],
+    }
+}
+
+/// PDCCH BLER in AWGN: DCI is QPSK with tail-biting convolutional code over
+/// `aggregation_level` CCEs. SINR at 50% BLER is Shannon limit of the coding rate
+/// plus coding gap, BLER curve is logistic around it
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PdcchLinkModel {
+    cce_count_table: CceCountTable,
+    diversity_gain_db: f32,
+    target_bler: f32,
+}
+
+impl PdcchLinkModel {
+    /// Model of the cell: bandwidth and PHICH define CCEs of every CFI, ports define
+    /// transmit diversity gain. Levels are chosen to keep BLER at most `target_bler`
+    pub fn new(cell_config: &CellConfig, target_bler: f32) -> Self {
+        let diversity_gain_db = match cell_config.nof_ports {
+            0 | 1 => TX_DIVERSITY_GAIN_DB[0],
+            2 => TX_DIVERSITY_GAIN_DB[1],
+            _ => TX_DIVERSITY_GAIN_DB[2],
+        };
+        PdcchLinkModel {
+            cce_count_table: resource_allocation::calculate_cce_count_table(cell_config),
+            diversity_gain_db,
+            target_bler,
+        }
+    }
+
+    /// Coding rate of DCI, `payload_bits` do not include CRC
+    pub fn coding_rate(aggregation_level: PdcchAggregation, payload_bits: u16) -> f32 {
+        (payload_bits + DCI_CRC_BITS) as f32 / (BITS_PER_CCE * aggregation_level.size() as f32)
+    }
+
+    /// SINR at which half of DCIs are lost
+    fn sinr_50_db(&self, aggregation_level: PdcchAggregation, payload_bits: u16) -> f32 {
+        // QPSK carries 2 bits per RE
+        let spectral_efficiency = 2.0 * Self::coding_rate(aggregation_level, payload_bits);
+        let shannon_sinr = 2f32.powf(spectral_efficiency) - 1.0;
+        10.0 * shannon_sinr.log10() + CODING_GAP_DB - self.diversity_gain_db
+    }
+
+    pub fn bler(
+        &self,
+        aggregation_level: PdcchAggregation,
+        payload_bits: u16,
+        sinr_db: f32,
+    ) -> f32 {
+        let margin_db = sinr_db - self.sinr_50_db(aggregation_level, payload_bits);
+        1.0 / (1.0 + (BLER_SLOPE_PER_DB * margin_db).exp())
+    }
+
+    /// SINR at which BLER reaches the target
+    pub fn required_sinr_db(&self, aggregation_level: PdcchAggregation, payload_bits: u16) -> f32 {
+        let odds = (1.0 - self.target_bler) / self.target_bler;
+        self.sinr_50_db(aggregation_level, payload_bits) + odds.ln() / BLER_SLOPE_PER_DB
+    }
+
+    /// Levels for DCI with `payload_bits` at `sinr_db`: the smallest level which reaches
+    /// the target BLER is preferred, larger levels are alternatives. Only levels which
+    /// fit into CCEs of `cfi` are used. If no level reaches the target, the largest one is
+    /// preferred
+    pub fn choose(&self, sinr_db: f32, payload_bits: u16, cfi: Cfi) -> AggregationChoice {
+        let cce_count = self.cce_count_table[cfi.index()] as usize;
+        let fitting = PdcchAggregation::list().map(|level| level.size() <= cce_count);
+        let preferred = PdcchAggregation::list()
+            .into_iter()
+            .filter(|level| fitting[level.index()])
+            .find(|level| self.required_sinr_db(*level, payload_bits) <= sinr_db)
+            .or(PdcchAggregation::list()
+                .into_iter()
+                .rev()
+                .find(|level| fitting[level.index()]))
+            .unwrap_or(PdcchAggregation::L1);
+        let mut alternatives = BoundedVec::new();
+        for level in PdcchAggregation::list() {
+            if level > preferred && fitting[level.index()] {
+                let _ = alternatives.push(level);
+            }
+        }
+        AggregationChoice::Levels {
+            preferred,
+            alternatives,
+        }
+    }
+}
+
+/// HARQ feedback of the PDSCH scheduled by DCI. ACK and NACK mean that the DCI was decoded
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HarqFeedback {
+    Ack,
+    Nack,
+    /// No feedback: UE missed the DCI
+    Dtx,
+}
+
+/// Outer loop of PDCCH link adaptation: offset added to reported SINR. Every missed DCI
+/// lowers the offset by a step, every decoded one raises it by `step * target / (1 - target)`,
+/// so the share of missed DCIs converges to the target BLER
+#[derive(Clone, Debug)]
+pub struct OuterLoop {
+    offset_db: f32,
+    step_up_db: f32,
+}
+
+impl OuterLoop {
+    pub fn new(target_bler: f32) -> Self {
+        OuterLoop {
+            offset_db: 0.0,
+            step_up_db: OFFSET_STEP_DOWN_DB * target_bler / (1.0 - target_bler),
+        }
+    }
+
+    pub fn offset_db(&self) -> f32 {
+        self.offset_db
+    }
+
+    /// Reported SINR corrected by the offset
+    pub fn sinr_db(&self, reported_sinr_db: f32) -> f32 {
+        reported_sinr_db + self.offset_db
+    }
+
+    pub fn update(&mut self, feedback: HarqFeedback) {
+        let step = match feedback {
+            HarqFeedback::Ack | HarqFeedback::Nack => self.step_up_db,
+            HarqFeedback::Dtx => -OFFSET_STEP_DOWN_DB,
+        };
+        self.offset_db = (self.offset_db + step).clamp(-MAX_OFFSET_DB, MAX_OFFSET_DB);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::allocator::test_fixture::cell;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+    use PdcchAggregation::*;
+
+    /// Format 1A at 20 MHz without CRC
+    const PAYLOAD_BITS: u16 = 28;
+
+    fn model(nof_prb: u8, nof_ports: u8) -> PdcchLinkModel {
+        let mut cell_config = cell(nof_prb);
+        cell_config.nof_ports = nof_ports;
+        PdcchLinkModel::new(&cell_config, 0.01)
+    }
+
+    fn levels(choice: AggregationChoice) -> Vec<PdcchAggregation> {
+        choice.levels().to_vec()
+    }
+
+    #[test]
+    fn bler() {
+        let model = model(100, 1);
+        for level in PdcchAggregation::list() {
+            let required = model.required_sinr_db(level, PAYLOAD_BITS);
+            assert!((model.bler(level, PAYLOAD_BITS, required) - 0.01).abs() < 1e-4);
+            assert!(model.bler(level, PAYLOAD_BITS, required - 3.0) > 0.1);
+        }
+        // Every doubling of CCEs and fewer payload bits need less SINR
+        let required = PdcchAggregation::list().map(|level| model.required_sinr_db(level, 28));
+        assert!(required.windows(2).all(|pair| pair[0] - pair[1] > 2.0));
+        assert!(model.required_sinr_db(L1, 28) > model.required_sinr_db(L1, 20));
+        assert!((4.0..6.0).contains(&required[0]), "{:?}", required);
+        assert!((-8.0..-4.0).contains(&required[3]), "{:?}", required);
+
+        // Transmit diversity
+        assert!(self::model(100, 4).required_sinr_db(L1, 28) < model.required_sinr_db(L1, 28));
+    }
+
+    #[test]
+    fn choose() {
+        let model = model(100, 1);
+        assert_eq!(
+            levels(model.choose(20.0, PAYLOAD_BITS, Cfi::One)),
+            [L1, L2, L4, L8]
+        );
+        assert_eq!(
+            levels(model.choose(cqi_sinr_db(4), PAYLOAD_BITS, Cfi::One)),
+            [L4, L8]
+        );
+        // Nothing reaches the target
+        assert_eq!(
+            levels(model.choose(cqi_sinr_db(0), PAYLOAD_BITS, Cfi::One)),
+            [L8]
+        );
+
+        // 6 PRB, CFI 2 has 2 CCEs
+        let model = self::model(6, 1);
+        assert_eq!(levels(model.choose(20.0, PAYLOAD_BITS, Cfi::Two)), [L1, L2]);
+        assert_eq!(
+            levels(model.choose(cqi_sinr_db(1), PAYLOAD_BITS, Cfi::Two)),
+            [L2]
+        );
+        assert_eq!(
+            levels(model.choose(cqi_sinr_db(1), PAYLOAD_BITS, Cfi::Four)),
+            [L4]
+        );
+
+        // Link quality choice is resolved by the model
+        let model = self::model(100, 1);
+        let from_cqi = |model: &PdcchLinkModel, cqi| {
+            levels(AggregationChoice::from_cqi(
+                model,
+                cqi,
+                PAYLOAD_BITS,
+                Cfi::One,
+            ))
+        };
+        assert_eq!(from_cqi(&model, 15), [L1, L2, L4, L8]);
+        assert_eq!(from_cqi(&model, 4), [L4, L8]);
+        assert_eq!(from_cqi(&model, 0), [L8]);
+        // Lower target BLER needs more CCEs
+        let mut cell_config = cell(100);
+        cell_config.nof_ports = 1;
+        let strict_model = PdcchLinkModel::new(&cell_config, 0.0001);
+        assert_eq!(from_cqi(&model, 7), [L1, L2, L4, L8]);
+        assert_eq!(from_cqi(&strict_model, 7), [L2, L4, L8]);
+    }
+
+    #[test]
+    fn outer_loop() {
+        let mut outer_loop = OuterLoop::new(0.01);
+        outer_loop.update(HarqFeedback::Dtx);
+        assert_eq!(outer_loop.offset_db(), -1.0);
+        outer_loop.update(HarqFeedback::Nack);
+        assert!(outer_loop.offset_db() > -1.0);
+        assert_eq!(outer_loop.sinr_db(5.0), 5.0 + outer_loop.offset_db());
+
+        // Reported SINR is 3 dB too optimistic, the loop finds the level which keeps BLER
+        let model = model(100, 1);
+        let reported_sinr_db = 3.0;
+        let true_sinr_db = reported_sinr_db - 3.0;
+        let mut outer_loop = OuterLoop::new(0.01);
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut missed = 0;
+        let count = 20_000;
+        for _ in 0..count {
+            let choice = model.choose(
+                outer_loop.sinr_db(reported_sinr_db),
+                PAYLOAD_BITS,
+                Cfi::Three,
+            );
+            let level = choice.levels()[0];
+            let feedback = if rng.gen::<f32>() < model.bler(level, PAYLOAD_BITS, true_sinr_db) {
+                missed += 1;
+                HarqFeedback::Dtx
+            } else {
+                HarqFeedback::Ack
+            };
+            outer_loop.update(feedback);
+        }
+        assert!(outer_loop.offset_db() < -1.0);
+        assert!((missed as f32 / count as f32) < 0.03, "{}", missed);
+    }
+}
@@ -1,5 +1,11 @@
-pub mod tree_pdcch_allocator;
+pub mod dci_format;
+pub mod link_adaptation;
+pub mod optimal_pdcch_allocator;
+pub mod pdcch_allocator;
 pub mod sequential_pdcch_allocator;
+pub mod servise_code;
 pub mod shuffling_pdcch_allocator;
 pub mod structures;
-pub mod servise_code;
\ No newline at end of file
+#[cfg(test)]
+pub(crate) mod test_fixture;
+pub mod tree_pdcch_allocator;
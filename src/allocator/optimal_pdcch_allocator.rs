@@ -0,0 +1,591 @@
+use crate::signalling::structs::{AllocationError, AllocationResult};
+use crate::types::bounded_vec::BoundedVec;
+use crate::types::cfi::Cfi;
+use std::cmp::Reverse;
+use std::sync::Arc;
+
+use super::dci_format::CodingRateLimit;
+use super::pdcch_allocator::{CfiBuffer, CfiState, PdcchAllocator};
+use super::structures::{
+    Budget, BudgetCounter, CceCountTable, CceMask, CfiPolicy, CfiSearchSpace, DciRequest,
+    PdcchAggregation, PdcchAllocation, SfSearchSpace, MAX_PDCCH,
+};
+
+/// Exact allocator: DCI is rejected only if there is no placement of all DCIs of the CFI.
+/// Search is depth-first, so memory does not depend on the number of combinations
+#[derive(Clone, Debug)]
+pub struct PdcchSchedOptimal {
+    state: CfiState<OptimalAllocation>,
+}
+
+impl PdcchSchedOptimal {
+    pub fn new(cce_table: CceCountTable) -> Self {
+        PdcchSchedOptimal {
+            state: CfiState::new(cce_table, OptimalAllocation::new),
+        }
+    }
+}
+
+impl PdcchAllocator for PdcchSchedOptimal {
+    fn new_tti(&mut self) {
+        self.state.new_tti();
+    }
+
+    fn current_cfi(&self) -> Cfi {
+        self.state.current_cfi
+    }
+
+    fn increment_cfi(&mut self) -> AllocationResult {
+        self.state.increment_cfi()
+    }
+
+    fn allocate_dci_in(&mut self, request: &DciRequest) -> Result<u8, AllocationError> {
+        self.state
+            .allocate(request, |buffer, dci, search_space, budget| {
+                buffer.try_alloc(dci, search_space, budget)
+            })
+    }
+
+    fn release_dci(&mut self, dci_index: u8) -> AllocationResult {
+        self.state.release_dci(dci_index)
+    }
+
+    /// Exact version of batch allocation: subset with the largest total weight for every CFI
+    /// found by branch-and-bound, the lowest CFI wins when weights are equal. DCIs refused by
+    /// the coding rate limit are not placed. Out of budget the best subset found so far
+    /// is allocated
+    fn allocate_batch(&mut self, dcis: &[DciRequest]) -> Vec<Option<u8>> {
+        // Candidates of the search space type of every DCI allowed by the coding rate limit
+        let common_search_space = Arc::clone(&self.state.common_search_space);
+        let (indices, selected): (Vec<usize>, Vec<DciRequest>) = dcis
+            .iter()
+            .enumerate()
+            .filter(|(_, dci)| self.state.allows(dci))
+            .map(|(i, dci)| {
+                let search_space = dci
+                    .search_space_type
+                    .select(&dci.search_space, &common_search_space);
+                let dci = DciRequest {
+                    search_space,
+                    ..*dci
+                };
+                (i, dci)
+            })
+            .unzip();
+
+        let state = &mut self.state;
+        let dci_count = state.buffers[state.current_cfi.index()].dci_count();
+        let mut best: Option<(u32, Vec<usize>)> = None;
+        for cfi in state.current_cfi.iter_to(state.max_cfi) {
+            let buffer = &state.buffers[cfi.index()];
+            if buffer.dci_count() != dci_count {
+                continue;
+            }
+            let (weight, subset) = buffer.max_weight_subset(&selected, cfi, &mut state.budget);
+            if best
+                .as_ref()
+                .is_none_or(|(best_weight, _)| weight > *best_weight)
+            {
+                best = Some((weight, subset));
+            }
+        }
+
+        // Every prefix of the subset fits, so allocation in the subset order succeeds unless
+        // the budget runs out
+        let mut dci_indices = vec![None; dcis.len()];
+        for k in best.map(|(_, subset)| subset).unwrap_or_default() {
+            let i = indices[k];
+            dci_indices[i] = self.allocate_dci_in(&dcis[i]).ok();
+        }
+        dci_indices
+    }
+
+    fn checkpoint(&mut self) {
+        self.state.checkpoint();
+    }
+
+    fn commit(&mut self) -> AllocationResult {
+        self.state.commit()
+    }
+
+    fn rollback(&mut self) -> AllocationResult {
+        self.state.rollback()
+    }
+
+    fn get_allocs(&self) -> (BoundedVec<PdcchAllocation, MAX_PDCCH>, CceMask, Cfi) {
+        self.state.get_allocs()
+    }
+
+    fn common_search_space(&self) -> &SfSearchSpace {
+        &self.state.common_search_space
+    }
+
+    fn set_cfi_policy(&mut self, cfi_policy: CfiPolicy) {
+        self.state.set_cfi_policy(cfi_policy);
+    }
+
+    fn set_coding_rate_limit(&mut self, coding_rate_limit: Option<CodingRateLimit>) {
+        self.state.set_coding_rate_limit(coding_rate_limit);
+    }
+
+    fn set_budget(&mut self, budget: Budget) {
+        self.state.budget.set_budget(budget);
+    }
+
+    fn exhaustive(&self) -> bool {
+        self.state.budget.exhaustive()
+    }
+}
+
+#[derive(Clone, Debug)]
+struct OptimalAllocation {
+    cce_count: u8,
+
+    /// Vector of allocations with their search spaces, DCIs may be moved inside them
+    allocation_buffer: BoundedVec<(PdcchAllocation, CfiSearchSpace), MAX_PDCCH>,
+    total_mask: CceMask,
+}
+
+impl OptimalAllocation {
+    fn new(cce_count: u8) -> OptimalAllocation {
+        OptimalAllocation {
+            cce_count,
+            allocation_buffer: BoundedVec::new(),
+            total_mask: CceMask::new(cce_count as usize),
+        }
+    }
+
+    fn update_total_mask(&mut self) {
+        self.total_mask = CceMask::new(self.cce_count as usize);
+        for (alloc, _) in self.allocation_buffer.iter() {
+            self.total_mask |= alloc.mask;
+        }
+    }
+
+    /// Candidate positions of every placed DCI
+    fn placed_candidates(&self) -> Vec<(PdcchAggregation, &[u8])> {
+        self.allocation_buffer
+            .iter()
+            .map(|(alloc, search_space)| {
+                (
+                    alloc.aggregation_level,
+                    &search_space[alloc.aggregation_level.index()][..],
+                )
+            })
+            .collect()
+    }
+
+    fn try_alloc(
+        &mut self,
+        dci: &PdcchAllocation,
+        search_space: &CfiSearchSpace,
+        budget: &mut BudgetCounter,
+    ) -> Result<(), ()> {
+        if self.allocation_buffer.is_full() {
+            return Err(());
+        }
+        let aggregation_level = dci.aggregation_level;
+
+        // Free candidate keeps placed DCIs where they are
+        let free_position = search_space[aggregation_level.index()]
+            .iter()
+            .filter_map(|&start_cce| {
+                position_mask(self.cce_count, aggregation_level, start_cce)
+                    .map(|mask| (start_cce, mask))
+            })
+            .find(|(_, mask)| (*mask & self.total_mask).none());
+        if let Some((start_cce, mask)) = free_position {
+            let alloc = PdcchAllocation {
+                start_cce,
+                mask,
+                ..dci.clone()
+            };
+            self.allocation_buffer
+                .push((alloc, search_space.clone()))
+                .map_err(|_| ())?;
+            self.total_mask |= mask;
+            return Ok(());
+        }
+
+        // Otherwise all DCIs are placed again
+        let mut candidates = self.placed_candidates();
+        candidates.push((
+            aggregation_level,
+            &search_space[aggregation_level.index()][..],
+        ));
+        let positions = find_placement_within(self.cce_count, &candidates, budget).ok_or(())?;
+
+        self.allocation_buffer
+            .push((dci.clone(), search_space.clone()))
+            .map_err(|_| ())?;
+        for ((alloc, _), start_cce) in self.allocation_buffer.iter_mut().zip(positions) {
+            alloc.start_cce = start_cce;
+            alloc.mask = position_mask(self.cce_count, alloc.aggregation_level, start_cce).unwrap();
+        }
+        self.update_total_mask();
+        Ok(())
+    }
+
+    /// Subset of `dcis` with the largest total weight which fits together with placed DCIs,
+    /// at most MAX_PDCCH DCIs in total. DCIs are taken in descending weight, branch is cut when the remaining weight can not
+    /// beat the best subset
+    fn max_weight_subset(
+        &self,
+        dcis: &[DciRequest],
+        cfi: Cfi,
+        budget: &mut BudgetCounter,
+    ) -> (u32, Vec<usize>) {
+        let mut order: Vec<usize> = (0..dcis.len()).collect();
+        order.sort_by_key(|&i| Reverse(dcis[i].weight()));
+
+        // Weight of order[k..]
+        let mut remaining_weight = vec![0; order.len() + 1];
+        for k in (0..order.len()).rev() {
+            remaining_weight[k] = remaining_weight[k + 1] + dcis[order[k]].weight();
+        }
+
+        let mut candidates = self.placed_candidates();
+        let mut chosen = Vec::new();
+        let mut best = (0, Vec::new());
+        self.branch(
+            dcis,
+            cfi,
+            &order,
+            &remaining_weight,
+            &mut candidates,
+            &mut chosen,
+            0,
+            &mut best,
+            budget,
+        );
+        best
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn branch<'a>(
+        &self,
+        dcis: &'a [DciRequest],
+        cfi: Cfi,
+        order: &[usize],
+        remaining_weight: &[u32],
+        candidates: &mut Vec<(PdcchAggregation, &'a [u8])>,
+        chosen: &mut Vec<usize>,
+        weight: u32,
+        best: &mut (u32, Vec<usize>),
+        budget: &mut BudgetCounter,
+    ) {
+        if weight > best.0 {
+            *best = (weight, chosen.clone());
+        }
+        let Some((&i, rest)) = order.split_first() else {
+            return;
+        };
+        if weight + remaining_weight[0] <= best.0 || budget.is_exhausted() {
+            return;
+        }
+
+        let dci = &dcis[i];
+        if candidates.len() < MAX_PDCCH {
+            candidates.push((
+                dci.aggregation_level,
+                &dci.search_space[cfi.index()][dci.aggregation_level.index()][..],
+            ));
+            if find_placement_within(self.cce_count, candidates, budget).is_some() {
+                chosen.push(i);
+                self.branch(
+                    dcis,
+                    cfi,
+                    rest,
+                    &remaining_weight[1..],
+                    candidates,
+                    chosen,
+                    weight + dci.weight(),
+                    best,
+                    budget,
+                );
+                chosen.pop();
+            }
+            candidates.pop();
+        }
+
+        self.branch(
+            dcis,
+            cfi,
+            rest,
+            &remaining_weight[1..],
+            candidates,
+            chosen,
+            weight,
+            best,
+            budget,
+        );
+    }
+}
+
+impl CfiBuffer for OptimalAllocation {
+    type Checkpoint = Self;
+
+    fn reset(&mut self) {
+        self.allocation_buffer.clear();
+        self.total_mask = CceMask::new(self.cce_count as usize);
+    }
+
+    fn dci_count(&self) -> usize {
+        self.allocation_buffer.len()
+    }
+
+    fn contains(&self, dci_index: u8) -> bool {
+        self.allocation_buffer
+            .iter()
+            .any(|(alloc, _)| alloc.dci_index == dci_index)
+    }
+
+    fn release(&mut self, dci_index: u8, _budget: &mut BudgetCounter) {
+        self.allocation_buffer
+            .retain(|(alloc, _)| alloc.dci_index != dci_index);
+        self.update_total_mask();
+    }
+
+    fn checkpoint(&mut self) -> Self {
+        self.clone()
+    }
+
+    fn restore(&mut self, checkpoint: Self) {
+        *self = checkpoint;
+    }
+
+    fn get_allocs(&self) -> (BoundedVec<PdcchAllocation, MAX_PDCCH>, CceMask) {
+        let mut allocs = BoundedVec::new();
+        for (alloc, _) in self.allocation_buffer.iter() {
+            allocs.push(alloc.clone()).unwrap();
+        }
+        (allocs, self.total_mask)
+    }
+}
+
+/// CCEs of the candidate, `None` if it does not fit into `cce_count`
+pub(crate) fn position_mask(
+    cce_count: u8,
+    aggregation_level: PdcchAggregation,
+    start_cce: u8,
+) -> Option<CceMask> {
+    let mut mask = CceMask::new(cce_count as usize);
+    mask.fill(start_cce as usize, aggregation_level.size(), true)
+        .ok()
+        .map(|_| mask)
+}
+
+/// Start CCE for every DCI such that DCIs do not overlap, `None` if there is no such placement
+pub fn find_placement(cce_count: u8, dcis: &[(PdcchAggregation, &[u8])]) -> Option<Vec<u8>> {
+    find_placement_within(cce_count, dcis, &mut BudgetCounter::default())
+}
+
+/// `find_placement` which spends one step of the budget per search node, `None` also
+/// if the budget runs out
+pub(crate) fn find_placement_within(
+    cce_count: u8,
+    dcis: &[(PdcchAggregation, &[u8])],
+    budget: &mut BudgetCounter,
+) -> Option<Vec<u8>> {
+    let demand = dcis
+        .iter()
+        .map(|(aggregation_level, _)| aggregation_level.size())
+        .sum();
+    let mut remaining: Vec<usize> = (0..dcis.len()).collect();
+    let mut positions = vec![0; dcis.len()];
+    place_remaining(
+        cce_count,
+        dcis,
+        &mut remaining,
+        CceMask::new(cce_count as usize),
+        demand,
+        &mut positions,
+        budget,
+    )
+    .then_some(positions)
+}
+
+/// Depth-first search. DCI with the fewest free candidates goes next, branch is cut when
+/// some DCI has no free candidate or remaining DCIs need more CCEs than are free
+fn place_remaining(
+    cce_count: u8,
+    dcis: &[(PdcchAggregation, &[u8])],
+    remaining: &mut Vec<usize>,
+    mask: CceMask,
+    demand: usize,
+    positions: &mut [u8],
+    budget: &mut BudgetCounter,
+) -> bool {
+    if remaining.is_empty() {
+        return true;
+    }
+    if !budget.step() {
+        return false;
+    }
+    if demand > mask.size() - mask.count() {
+        return false;
+    }
+
+    let free_positions = |i: usize| {
+        let (aggregation_level, candidates) = dcis[i];
+        candidates.iter().filter_map(move |&start_cce| {
+            position_mask(cce_count, aggregation_level, start_cce)
+                .filter(|alloc_mask| (*alloc_mask & mask).none())
+                .map(|alloc_mask| (start_cce, alloc_mask))
+        })
+    };
+    let (k, free_count) = remaining
+        .iter()
+        .enumerate()
+        .map(|(k, &i)| (k, free_positions(i).count()))
+        .min_by_key(|&(_, free_count)| free_count)
+        .unwrap();
+    if free_count == 0 {
+        return false;
+    }
+
+    let i = remaining.swap_remove(k);
+    let size = dcis[i].0.size();
+    for (start_cce, alloc_mask) in free_positions(i) {
+        positions[i] = start_cce;
+        if place_remaining(
+            cce_count,
+            dcis,
+            remaining,
+            mask | alloc_mask,
+            demand - size,
+            positions,
+            budget,
+        ) {
+            return true;
+        }
+    }
+    remaining.push(i);
+    let last = remaining.len() - 1;
+    remaining.swap(k, last);
+    false
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::allocator::dci_format::{DciFormat, DciSizes};
+    use crate::allocator::test_fixture::{
+        allocators, cell, check_allocs, count_table, random_dcis,
+    };
+    use crate::allocator::tree_pdcch_allocator::PdcchSchedTree;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    fn weight(dcis: &[DciRequest], dci_indices: &[Option<u8>]) -> u32 {
+        dcis.iter()
+            .zip(dci_indices)
+            .filter(|(_, dci_index)| dci_index.is_some())
+            .map(|(dci, _)| dci.weight())
+            .sum()
+    }
+
+    #[test]
+    fn same_decisions_as_tree() {
+        // Tree keeps every placement, so it accepts exactly the same DCIs
+        let count_table = count_table(25);
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut tree = PdcchSchedTree::new(count_table);
+        let mut optimal = PdcchSchedOptimal::new(count_table);
+
+        for _ in 0..200 {
+            let dcis = random_dcis(&mut rng, &count_table, 7);
+            tree.new_tti();
+            optimal.new_tti();
+            for dci in dcis.iter() {
+                let tree_res =
+                    tree.allocate_dci(dci.aggregation_level, &dci.search_space, dci.rnti);
+                let optimal_res =
+                    optimal.allocate_dci(dci.aggregation_level, &dci.search_space, dci.rnti);
+                assert_eq!(tree_res, optimal_res);
+                assert_eq!(tree.current_cfi(), optimal.current_cfi());
+            }
+            check_allocs(&optimal);
+        }
+    }
+
+    #[test]
+    fn max_pdcch() {
+        let count_table = count_table(100);
+        let mut rng = StdRng::seed_from_u64(2);
+        let mut s = PdcchSchedOptimal::new(count_table);
+        s.new_tti();
+        let mut allocated = 0;
+        for dci in random_dcis(&mut rng, &count_table, MAX_PDCCH + 4) {
+            if s.allocate_dci(dci.aggregation_level, &dci.search_space, dci.rnti)
+                .is_ok()
+            {
+                allocated += 1;
+            }
+        }
+        assert_eq!(allocated, MAX_PDCCH);
+        check_allocs(&s);
+    }
+
+    #[test]
+    fn batch_oracle() {
+        let count_table = count_table(25);
+        let mut rng = StdRng::seed_from_u64(3);
+        let mut optimal = PdcchSchedOptimal::new(count_table);
+        let mut allocators = allocators(count_table);
+
+        for _ in 0..50 {
+            let dcis = random_dcis(&mut rng, &count_table, 8);
+            optimal.new_tti();
+            let best = weight(&dcis, &optimal.allocate_batch(&dcis));
+            check_allocs(&optimal);
+
+            for s in allocators.iter_mut() {
+                s.new_tti();
+                let dci_indices = s.allocate_batch(&dcis);
+                check_allocs(s.as_ref());
+                let heuristic = weight(&dcis, &dci_indices);
+                assert!(
+                    heuristic < best
+                        || (heuristic == best && s.current_cfi() >= optimal.current_cfi())
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn batch_coding_rate_limit() {
+        // F1A fits from L2, F2 from L4: DCIs refused at their level must not take the place
+        // of allowed ones
+        let count_table = count_table(25);
+        let limit = CodingRateLimit {
+            sizes: DciSizes::new(&cell(25)),
+            max_coding_rate: 0.3,
+        };
+        let mut rng = StdRng::seed_from_u64(4);
+        let mut optimal = PdcchSchedOptimal::new(count_table);
+        optimal.set_coding_rate_limit(Some(limit));
+        let mut allocators = allocators(count_table);
+        for s in allocators.iter_mut() {
+            s.set_coding_rate_limit(Some(limit));
+        }
+
+        for _ in 0..200 {
+            let dcis: Vec<_> = random_dcis(&mut rng, &count_table, 8)
+                .into_iter()
+                .map(|dci| DciRequest {
+                    format: [DciFormat::F1A, DciFormat::F2][rng.gen_range(0..2)],
+                    ..dci
+                })
+                .collect();
+            optimal.new_tti();
+            let best = weight(&dcis, &optimal.allocate_batch(&dcis));
+            check_allocs(&optimal);
+
+            for s in allocators.iter_mut() {
+                s.new_tti();
+                assert!(weight(&dcis, &s.allocate_batch(&dcis)) <= best);
+            }
+        }
+    }
+}
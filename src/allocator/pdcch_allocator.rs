@@ -0,0 +1,1220 @@
+use crate::defines;
+use crate::signalling::structs::{AllocationError, AllocationResult};
+use crate::types::bounded_vec::BoundedVec;
+use crate::types::cfi::Cfi;
+use crate::types::rnti::Rnti;
+use std::array;
+use std::cmp::Reverse;
+use std::fmt;
+use std::sync::Arc;
+
+use super::dci_format::CodingRateLimit;
+use super::optimal_pdcch_allocator::{find_placement_within, position_mask};
+use super::structures::{
+    calculate_common_search_space, cfi_range, AggregationChoice, BatchOrder, Budget, BudgetCounter,
+    CceCountTable, CceMask, CfiPolicy, CfiSearchSpace, CompactSearchSpace, DciRequest,
+    PdcchAggregation, PdcchAllocation, PdschPayload, PdschReTable, SfSearchSpace, MAX_PDCCH,
+};
+
+/// Common interface of PDCCH allocators. Allocator keeps DCI placement for one TTI
+pub trait PdcchAllocator {
+    /// Drops all allocations and resets CFI before the next TTI
+    fn new_tti(&mut self);
+
+    /// CFI required for the DCIs allocated so far
+    fn current_cfi(&self) -> Cfi;
+
+    fn increment_cfi(&mut self) -> AllocationResult;
+
+    /// Places DCI of format 1A into UE-specific search space. Returns index of the DCI inside TTI
+    fn allocate_dci(
+        &mut self,
+        aggregation_level: PdcchAggregation,
+        search_space: &SfSearchSpace,
+        rnti: Rnti,
+    ) -> Result<u8, AllocationError> {
+        self.allocate_dci_in(&DciRequest::new(aggregation_level, search_space, rnti))
+    }
+
+    /// Places DCI into the search space of its type. Priority of the DCI is used by
+    /// `allocate_dci_preempting` of later DCIs. Fails with `CodingRateTooHigh` if the coding
+    /// rate limit refuses the format of the DCI at its aggregation level
+    fn allocate_dci_in(&mut self, dci: &DciRequest) -> Result<u8, AllocationError>;
+
+    /// Same as `allocate_dci_in`, but tries the levels of `choice` in order until the DCI fits,
+    /// skipping levels refused by the coding rate limit. Aggregation level of `dci` is not used.
+    /// Returns index of the DCI and the level used
+    fn allocate_dci_adaptive(
+        &mut self,
+        choice: &AggregationChoice,
+        dci: &DciRequest,
+    ) -> Result<(u8, PdcchAggregation), AllocationError> {
+        let mut error = AllocationError::CodingRateTooHigh;
+        for aggregation_level in choice.levels() {
+            match self.allocate_dci_in(&dci.at_level(aggregation_level)) {
+                Ok(dci_index) => return Ok((dci_index, aggregation_level)),
+                Err(AllocationError::NoCchSpace) => error = AllocationError::NoCchSpace,
+                Err(AllocationError::CodingRateTooHigh) => {}
+                Err(err) => return Err(err),
+            }
+        }
+        Err(error)
+    }
+
+    /// Same as `allocate_dci_in`, but if there is no free place releases DCIs with lower priority
+    /// which block this DCI, lowest priority first. Returns index of the DCI and indices of
+    /// the released DCIs. Nothing is released if the DCI does not fit anyway or the budget runs out
+    fn allocate_dci_preempting(
+        &mut self,
+        dci: &DciRequest,
+    ) -> Result<(u8, BoundedVec<u8, MAX_PDCCH>), AllocationError> {
+        let priority = dci.priority;
+        let try_alloc = |s: &mut Self| s.allocate_dci_in(dci);
+        match try_alloc(self) {
+            Ok(dci_index) => return Ok((dci_index, BoundedVec::new())),
+            Err(AllocationError::NoCchSpace) => {}
+            // Releasing DCIs does not help
+            Err(err) => return Err(err),
+        }
+
+        // Latest DCI goes first among DCIs with the same priority
+        let (allocs, _, _) = self.get_allocs();
+        let mut candidates: Vec<&PdcchAllocation> = allocs
+            .iter()
+            .filter(|alloc| alloc.priority < priority)
+            .collect();
+        candidates.sort_by_key(|alloc| (alloc.priority, Reverse(alloc.dci_index)));
+
+        // Release candidates until the DCI fits
+        let mut preempted = BoundedVec::<u8, MAX_PDCCH>::new();
+        let mut fits = false;
+        self.checkpoint();
+        for alloc in candidates {
+            self.release_dci(alloc.dci_index)?;
+            let _ = preempted.push(alloc.dci_index);
+            self.checkpoint();
+            fits = try_alloc(self).is_ok();
+            self.rollback()?;
+            if fits {
+                break;
+            }
+        }
+        self.rollback()?;
+        if !fits {
+            return Err(AllocationError::NoCchSpace);
+        }
+
+        // Keep released DCIs which do not block this one, higher priority first
+        for i in (0..preempted.len()).rev() {
+            let dci_index = preempted.remove(i);
+            self.checkpoint();
+            for &released in preempted.iter() {
+                self.release_dci(released)?;
+            }
+            let fits = try_alloc(self).is_ok();
+            self.rollback()?;
+            if !fits {
+                let _ = preempted.push(dci_index);
+                preempted[i..].rotate_right(1);
+            }
+        }
+
+        // Budget may run out since the probes above, then released DCIs are restored
+        self.checkpoint();
+        for &released in preempted.iter() {
+            self.release_dci(released)?;
+        }
+        match try_alloc(self) {
+            Ok(dci_index) => {
+                self.commit()?;
+                Ok((dci_index, preempted))
+            }
+            Err(err) => {
+                self.rollback()?;
+                Err(err)
+            }
+        }
+    }
+
+    /// Frees CCEs of the DCI with index returned by `allocate_dci`. CFI goes back
+    /// to the lowest one which holds all remaining DCIs
+    fn release_dci(&mut self, dci_index: u8) -> AllocationResult;
+
+    /// Places DCIs of the TTI one by one in the given order, starting from the current state.
+    /// Returns index of every DCI, `None` for DCIs which were not placed
+    fn allocate_batch_in_order(
+        &mut self,
+        dcis: &[DciRequest],
+        order: BatchOrder,
+    ) -> Vec<Option<u8>> {
+        let mut dci_indices = vec![None; dcis.len()];
+        let mut remaining = order.sort(dcis, self.common_search_space(), self.get_allocs().2);
+        while !remaining.is_empty() {
+            let next = match order {
+                BatchOrder::MostConstrainedFirst if remaining.len() > 1 => {
+                    // CFI and occupied CCEs of this step
+                    let (_, mask, cfi) = self.get_allocs();
+                    let common_search_space = self.common_search_space();
+                    (0..remaining.len())
+                        .min_by_key(|&i| {
+                            let dci = &dcis[remaining[i]];
+                            let free_positions =
+                                dci.free_position_count(common_search_space, &mask, cfi);
+                            (Reverse(dci.priority), free_positions)
+                        })
+                        .unwrap()
+                }
+                _ => 0,
+            };
+            let i = remaining.remove(next);
+            dci_indices[i] = self.allocate_dci_in(&dcis[i]).ok();
+        }
+        dci_indices
+    }
+
+    /// Places DCIs of the TTI with every `BatchOrder` and keeps the subset with the largest
+    /// total weight, then with the lowest CFI of `get_allocs`. Returns index of every DCI, `None`
+    /// if not placed
+    fn allocate_batch(&mut self, dcis: &[DciRequest]) -> Vec<Option<u8>> {
+        let mut best = None;
+        for order in BatchOrder::list() {
+            self.checkpoint();
+            let dci_indices = self.allocate_batch_in_order(dcis, order);
+            let weight: u32 = dcis
+                .iter()
+                .zip(dci_indices.iter())
+                .filter(|(_, dci_index)| dci_index.is_some())
+                .map(|(dci, _)| dci.weight())
+                .sum();
+            let score = (weight, Reverse(self.get_allocs().2));
+            self.rollback().expect("checkpoint is saved above");
+
+            if best.is_none_or(|(best_score, _)| score > best_score) {
+                best = Some((score, order));
+            }
+        }
+        let (_, order) = best.unwrap();
+        self.allocate_batch_in_order(dcis, order)
+    }
+
+    /// Chooses CFI together with PDSCH: a bigger CFI fits more DCIs, but leaves fewer REs for
+    /// their PDSCH in `nof_prb` PRBs. For every CFI from the current one DCIs are placed
+    /// by priority, then largest payload first, skipping DCIs whose PDSCH does not fit at
+    /// this CFI or has no PRB count. Keeps the subset with the most scheduled bits, then with
+    /// the lowest CFI. `payloads[i]` is the PDSCH of `dcis[i]`, the lengths must be equal.
+    /// `re_table` comes from `calculate_pdsch_re_table`. Returns the CFI of `get_allocs` and
+    /// index of every DCI, `None` if not placed
+    fn allocate_batch_with_pdsch(
+        &mut self,
+        dcis: &[DciRequest],
+        payloads: &[PdschPayload],
+        nof_prb: u8,
+        re_table: &PdschReTable,
+    ) -> (Cfi, Vec<Option<u8>>) {
+        assert_eq!(
+            dcis.len(),
+            payloads.len(),
+            "every DCI needs its PDSCH payload"
+        );
+        let mut order: Vec<usize> = (0..dcis.len()).collect();
+        order.sort_by_key(|&i| (Reverse(dcis[i].priority), Reverse(payloads[i].bits)));
+
+        let mut best = None;
+        for cfi in self.current_cfi().iter() {
+            if re_table[cfi.index()] == 0 {
+                break;
+            }
+            self.checkpoint();
+            let (dci_indices, all_placed) =
+                allocate_with_pdsch_at(self, dcis, payloads, &order, nof_prb, re_table, cfi);
+            let bits: u64 = payloads
+                .iter()
+                .zip(dci_indices.iter())
+                .filter(|(_, dci_index)| dci_index.is_some())
+                .map(|(payload, _)| payload.bits as u64)
+                .sum();
+            let score = (bits, Reverse(self.get_allocs().2));
+            self.rollback().expect("checkpoint is saved above");
+
+            if best.is_none_or(|(best_score, _)| score > best_score) {
+                best = Some((score, cfi));
+            }
+            // Bigger CFI only takes REs from PDSCH
+            if all_placed {
+                break;
+            }
+        }
+        let Some((_, cfi)) = best else {
+            return (self.get_allocs().2, vec![None; dcis.len()]);
+        };
+        let (dci_indices, _) =
+            allocate_with_pdsch_at(self, dcis, payloads, &order, nof_prb, re_table, cfi);
+        (self.get_allocs().2, dci_indices)
+    }
+
+    /// Saves allocator state. DCIs allocated or released after the checkpoint are either
+    /// kept by `commit` or reverted by `rollback`. Checkpoints can be nested
+    fn checkpoint(&mut self);
+
+    /// Drops the last checkpoint and keeps the changes made after it
+    fn commit(&mut self) -> AllocationResult;
+
+    /// Restores the state saved by the last checkpoint
+    fn rollback(&mut self) -> AllocationResult;
+
+    /// Allocated DCIs, occupied CCEs and CFI. CFI is the lowest one allowed by the policy at
+    /// which all DCIs fit, it may be lower than `current_cfi`, then DCIs are placed again
+    fn get_allocs(&self) -> (BoundedVec<PdcchAllocation, MAX_PDCCH>, CceMask, Cfi);
+
+    /// Common search space, candidates of DCIs with `SearchSpaceType::Common` and `Any`
+    fn common_search_space(&self) -> &SfSearchSpace;
+
+    /// CFI policy of the next TTIs, applied from the next `new_tti`
+    fn set_cfi_policy(&mut self, cfi_policy: CfiPolicy);
+
+    /// Refuses aggregation levels at which coding rate of the DCI format is above the limit,
+    /// `None` allows any level
+    fn set_coding_rate_limit(&mut self, coding_rate_limit: Option<CodingRateLimit>);
+
+    /// Limits the work in every TTI, counted from this call and from every `new_tti`.
+    /// Out of budget the allocator keeps the allocations found so far and places the next
+    /// DCIs without search
+    fn set_budget(&mut self, budget: Budget);
+
+    /// False if the budget or a size limit cut the search in this TTI. Allocations are valid
+    /// anyway, but some DCIs may be rejected although they fit
+    fn exhaustive(&self) -> bool;
+}
+
+/// Places DCIs in `order` so that CFI stays at most `cfi` and their PDSCH fits into `nof_prb`
+/// PRBs at `cfi`. Also returns whether no DCI was rejected by PDCCH
+fn allocate_with_pdsch_at<A: PdcchAllocator + ?Sized>(
+    allocator: &mut A,
+    dcis: &[DciRequest],
+    payloads: &[PdschPayload],
+    order: &[usize],
+    nof_prb: u8,
+    re_table: &PdschReTable,
+    cfi: Cfi,
+) -> (Vec<Option<u8>>, bool) {
+    let mut dci_indices = vec![None; dcis.len()];
+    let mut all_placed = true;
+    let mut free_prb = nof_prb as u32;
+    for &i in order {
+        let Some(prb_count) = payloads[i]
+            .prb_count(re_table[cfi.index()])
+            .filter(|&prb_count| prb_count <= free_prb)
+        else {
+            continue;
+        };
+        allocator.checkpoint();
+        match allocator.allocate_dci_in(&dcis[i]) {
+            Ok(dci_index) if allocator.current_cfi() <= cfi => {
+                allocator.commit().expect("checkpoint is saved above");
+                dci_indices[i] = Some(dci_index);
+                free_prb -= prb_count;
+            }
+            _ => {
+                allocator.rollback().expect("checkpoint is saved above");
+                all_placed = false;
+            }
+        }
+    }
+    (dci_indices, all_placed)
+}
+
+/// Buffer of DCIs placed at one CFI. Allocators keep a buffer for every CFI
+pub(crate) trait CfiBuffer {
+    /// State saved by `checkpoint`
+    type Checkpoint: Clone + fmt::Debug;
+
+    fn reset(&mut self);
+
+    fn dci_count(&self) -> usize;
+
+    fn contains(&self, dci_index: u8) -> bool;
+
+    fn release(&mut self, dci_index: u8, budget: &mut BudgetCounter);
+
+    fn checkpoint(&mut self) -> Self::Checkpoint;
+
+    /// Keeps the changes made after the checkpoint
+    fn commit(&mut self, _checkpoint: Self::Checkpoint) {}
+
+    fn restore(&mut self, checkpoint: Self::Checkpoint);
+
+    fn get_allocs(&self) -> (BoundedVec<PdcchAllocation, MAX_PDCCH>, CceMask);
+}
+
+/// Saved (current CFI, DCI index, per-CFI buffer checkpoints)
+type CfiCheckpoint<B> = (Cfi, u8, [<B as CfiBuffer>::Checkpoint; defines::NUM_OF_CFI]);
+
+/// State shared by the allocators: DCI is placed into the buffer of every CFI which holds all
+/// previous DCIs, the lowest of them is the current CFI. Allocators differ only in the way
+/// a buffer places DCI
+#[derive(Clone, Debug)]
+pub(crate) struct CfiState<B: CfiBuffer> {
+    pub(crate) current_cfi: Cfi,
+    dci_index: u8,
+
+    /// CFIs allowed in this TTI: CFIs with CCEs for PDCCH in this cell limited by the policy
+    min_cfi: Cfi,
+    pub(crate) max_cfi: Cfi,
+    cfi_policy: CfiPolicy,
+    cce_table: CceCountTable,
+    /// Aggregation levels refused for DCI formats, `None` if any level is allowed
+    coding_rate_limit: Option<CodingRateLimit>,
+
+    /// Search space of every allocated DCI by its index, to place DCIs at a lower CFI
+    search_spaces: Vec<CompactSearchSpace>,
+
+    pub(crate) buffers: [B; defines::NUM_OF_CFI],
+
+    pub(crate) common_search_space: Arc<SfSearchSpace>,
+
+    pub(crate) budget: BudgetCounter,
+
+    checkpoints: Vec<CfiCheckpoint<B>>,
+}
+
+impl<B: CfiBuffer> CfiState<B> {
+    /// State with `buffer(cce_count)` for every CFI
+    pub(crate) fn new(cce_table: CceCountTable, buffer: impl Fn(u8) -> B) -> Self {
+        let (min_cfi, max_cfi) = cfi_range(&cce_table);
+        CfiState {
+            current_cfi: min_cfi,
+            dci_index: 0,
+            min_cfi,
+            max_cfi,
+            cfi_policy: CfiPolicy::default(),
+            cce_table,
+            coding_rate_limit: None,
+            search_spaces: Vec::new(),
+            buffers: array::from_fn(|i| buffer(cce_table[i])),
+            common_search_space: Arc::new(calculate_common_search_space(&cce_table)),
+            budget: BudgetCounter::default(),
+            checkpoints: Vec::new(),
+        }
+    }
+
+    pub(crate) fn new_tti(&mut self) {
+        let last_cfi = match self.cfi_policy {
+            CfiPolicy::LastTti => self.get_allocs().2,
+            _ => self.current_cfi,
+        };
+        let (min_cfi, max_cfi) = cfi_range(&self.cce_table);
+        (self.min_cfi, self.max_cfi) = self.cfi_policy.cfi_range(min_cfi, max_cfi, last_cfi);
+        self.current_cfi = self.min_cfi;
+        self.dci_index = 0;
+        self.search_spaces.clear();
+        self.checkpoints.clear();
+        self.budget.new_tti();
+        for buffer in self.buffers.iter_mut() {
+            buffer.reset();
+        }
+    }
+
+    pub(crate) fn increment_cfi(&mut self) -> AllocationResult {
+        // Buffers which missed one of the DCIs can not be used
+        let dci_count = self.buffers[self.current_cfi.index()].dci_count();
+        let mut cfi = self.current_cfi;
+        while let Some(next_cfi) = cfi.next().filter(|&cfi| cfi <= self.max_cfi) {
+            cfi = next_cfi;
+            if self.buffers[cfi.index()].dci_count() == dci_count {
+                self.current_cfi = cfi;
+                return Ok(());
+            }
+        }
+        Err(AllocationError::NoCchSpace)
+    }
+
+    /// Places DCI with `try_alloc(buffer, dci, candidates of the CFI, budget)` into buffers
+    pub(crate) fn allocate(
+        &mut self,
+        request: &DciRequest,
+        mut try_alloc: impl FnMut(
+            &mut B,
+            &PdcchAllocation,
+            &CfiSearchSpace,
+            &mut BudgetCounter,
+        ) -> Result<(), ()>,
+    ) -> Result<u8, AllocationError> {
+        if !self.allows(request) {
+            return Err(AllocationError::CodingRateTooHigh);
+        }
+        let common_search_space = Arc::clone(&self.common_search_space);
+        let search_space = request
+            .search_space_type
+            .select(&request.search_space, &common_search_space);
+        let dci = PdcchAllocation {
+            aggregation_level: request.aggregation_level,
+            format: request.format,
+            rnti: request.rnti,
+            carrier_indicator: request.carrier_indicator,
+            dci_index: self.dci_index,
+            priority: request.priority,
+            ..Default::default()
+        };
+        let dci_count = self.buffers[self.current_cfi.index()].dci_count();
+        let mut allocated_cfi = None;
+        for cfi in self.current_cfi.iter_to(self.max_cfi) {
+            let buffer = &mut self.buffers[cfi.index()];
+            if buffer.dci_count() != dci_count {
+                continue;
+            }
+            let res = try_alloc(buffer, &dci, &search_space[cfi.index()], &mut self.budget);
+            if res.is_ok() && allocated_cfi.is_none() {
+                allocated_cfi = Some(cfi);
+            }
+        }
+
+        self.current_cfi = allocated_cfi.ok_or(AllocationError::NoCchSpace)?;
+        self.search_spaces.truncate(self.dci_index as usize);
+        self.search_spaces
+            .push(CompactSearchSpace::below(&search_space, self.max_cfi));
+        self.dci_index += 1;
+        Ok(self.dci_index - 1)
+    }
+
+    /// False if the coding rate limit refuses the format of the DCI at its aggregation level
+    pub(crate) fn allows(&self, request: &DciRequest) -> bool {
+        self.coding_rate_limit
+            .is_none_or(|limit| limit.allows(request.format, request.aggregation_level))
+    }
+
+    pub(crate) fn release_dci(&mut self, dci_index: u8) -> AllocationResult {
+        if !self.buffers[self.current_cfi.index()].contains(dci_index) {
+            return Err(AllocationError::InvalidParameter);
+        }
+        for buffer in self.buffers.iter_mut() {
+            buffer.release(dci_index, &mut self.budget);
+        }
+
+        // Lower CFI may hold all remaining DCIs again
+        let dci_count = self.buffers[self.current_cfi.index()].dci_count();
+        if let Some(cfi) = self
+            .min_cfi
+            .iter_to(self.current_cfi)
+            .find(|cfi| self.buffers[cfi.index()].dci_count() == dci_count)
+        {
+            self.current_cfi = cfi;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn checkpoint(&mut self) {
+        let checkpoints = array::from_fn(|i| self.buffers[i].checkpoint());
+        self.checkpoints
+            .push((self.current_cfi, self.dci_index, checkpoints));
+    }
+
+    pub(crate) fn commit(&mut self) -> AllocationResult {
+        let (_, _, checkpoints) = self
+            .checkpoints
+            .pop()
+            .ok_or(AllocationError::InvalidParameter)?;
+        for (buffer, checkpoint) in self.buffers.iter_mut().zip(checkpoints) {
+            buffer.commit(checkpoint);
+        }
+        Ok(())
+    }
+
+    pub(crate) fn rollback(&mut self) -> AllocationResult {
+        let (current_cfi, dci_index, checkpoints) = self
+            .checkpoints
+            .pop()
+            .ok_or(AllocationError::InvalidParameter)?;
+        self.current_cfi = current_cfi;
+        self.dci_index = dci_index;
+        for (buffer, checkpoint) in self.buffers.iter_mut().zip(checkpoints) {
+            buffer.restore(checkpoint);
+        }
+        Ok(())
+    }
+
+    pub(crate) fn get_allocs(&self) -> (BoundedVec<PdcchAllocation, MAX_PDCCH>, CceMask, Cfi) {
+        let (allocs, mask) = self.buffers[self.current_cfi.index()].get_allocs();
+        // Lower CFIs missed one of the DCIs on the way, but the final set may fit there
+        self.place_at_lowest_cfi(&allocs, &mut self.budget.clone())
+            .unwrap_or((allocs, mask, self.current_cfi))
+    }
+
+    /// Allocations placed again at the lowest CFI below the current one allowed by the policy
+    /// at which all of them fit. `None` if no lower CFI fits or the budget runs out
+    fn place_at_lowest_cfi(
+        &self,
+        allocs: &BoundedVec<PdcchAllocation, MAX_PDCCH>,
+        budget: &mut BudgetCounter,
+    ) -> Option<(BoundedVec<PdcchAllocation, MAX_PDCCH>, CceMask, Cfi)> {
+        let min_cfi = match self.cfi_policy {
+            CfiPolicy::Fixed(_) => return None,
+            CfiPolicy::LastTti => cfi_range(&self.cce_table).0,
+            CfiPolicy::Minimize | CfiPolicy::Cap(_) => self.min_cfi,
+        };
+        for cfi in min_cfi.iter().take_while(|&cfi| cfi < self.current_cfi) {
+            let cce_count = self.cce_table[cfi.index()];
+            let dcis: Vec<_> = allocs
+                .iter()
+                .map(|alloc| {
+                    let search_space = &self.search_spaces[alloc.dci_index as usize];
+                    (
+                        alloc.aggregation_level,
+                        search_space.get(cfi, alloc.aggregation_level),
+                    )
+                })
+                .collect();
+            let Some(positions) = find_placement_within(cce_count, &dcis, budget) else {
+                continue;
+            };
+
+            let mut lower_allocs = BoundedVec::new();
+            let mut total_mask = CceMask::new(cce_count as usize);
+            for (alloc, start_cce) in allocs.iter().zip(positions) {
+                let mask = position_mask(cce_count, alloc.aggregation_level, start_cce).unwrap();
+                total_mask |= mask;
+                let alloc = PdcchAllocation {
+                    start_cce,
+                    mask,
+                    ..alloc.clone()
+                };
+                lower_allocs.push(alloc).unwrap();
+            }
+            return Some((lower_allocs, total_mask, cfi));
+        }
+        None
+    }
+
+    pub(crate) fn set_cfi_policy(&mut self, cfi_policy: CfiPolicy) {
+        self.cfi_policy = cfi_policy;
+    }
+
+    pub(crate) fn set_coding_rate_limit(&mut self, coding_rate_limit: Option<CodingRateLimit>) {
+        self.coding_rate_limit = coding_rate_limit;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::allocator::dci_format::DciFormat;
+    use crate::allocator::optimal_pdcch_allocator::PdcchSchedOptimal;
+    use crate::allocator::shuffling_pdcch_allocator::{Displacement, PdcchSchedShuffling};
+    use crate::allocator::structures::{calculate_search_space, StartPosition};
+    use crate::allocator::test_fixture::{
+        allocators, cell, check_allocs, count_table, dci, random_dcis, start_position_allocators,
+    };
+    use crate::allocator::tree_pdcch_allocator::PdcchSchedTree;
+    use crate::resource_allocation;
+    use crate::types::tti::TtiPoint;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    #[test]
+    fn release_dci() {
+        let count_table = count_table(6);
+
+        let tti = TtiPoint::from(1);
+        let rntis = [Rnti(10249), Rnti(7386), Rnti(45236)];
+        for mut s in allocators(count_table) {
+            s.new_tti();
+            let mut dci_indices = Vec::new();
+            for rnti in rntis {
+                let search_space = calculate_search_space(&rnti, &count_table);
+                dci_indices.push(
+                    s.allocate_dci(
+                        PdcchAggregation::L2,
+                        &search_space[tti.to_usize() % 10],
+                        rnti,
+                    )
+                    .unwrap(),
+                );
+            }
+            assert_eq!(s.current_cfi(), Cfi::Four);
+            assert_eq!(s.release_dci(7), Err(AllocationError::InvalidParameter));
+
+            s.release_dci(dci_indices[1]).unwrap();
+            let (allocs, mask, _) = s.get_allocs();
+            assert_eq!(allocs.len(), 2);
+            assert!(allocs.iter().all(|alloc| alloc.dci_index != dci_indices[1]));
+            let mut allocs_mask = CceMask::new(mask.size());
+            for alloc in allocs.iter() {
+                allocs_mask |= *alloc.mask();
+            }
+            assert_eq!(mask, allocs_mask);
+            assert_eq!(mask.count(), 4);
+            assert_eq!(
+                s.release_dci(dci_indices[1]),
+                Err(AllocationError::InvalidParameter)
+            );
+
+            // Freed CCEs are available for the next DCI
+            let search_space = calculate_search_space(&rntis[1], &count_table);
+            s.allocate_dci(
+                PdcchAggregation::L2,
+                &search_space[tti.to_usize() % 10],
+                rntis[1],
+            )
+            .unwrap();
+            assert_eq!(s.get_allocs().1.count(), 6);
+
+            for dci_index in [dci_indices[0], dci_indices[2], 3] {
+                s.release_dci(dci_index).unwrap();
+            }
+            let (allocs, mask, cfi) = s.get_allocs();
+            assert!(allocs.is_empty());
+            assert!(mask.none());
+            assert_eq!(cfi, Cfi::Two);
+        }
+    }
+
+    #[test]
+    fn checkpoint_rollback() {
+        let count_table = count_table(6);
+
+        let tti = TtiPoint::from(1);
+        let rntis = [Rnti(10249), Rnti(7386), Rnti(45236)];
+        let search_spaces: Vec<_> = rntis
+            .iter()
+            .map(|rnti| calculate_search_space(rnti, &count_table))
+            .collect();
+        for mut s in allocators(count_table) {
+            s.new_tti();
+            assert_eq!(s.rollback(), Err(AllocationError::InvalidParameter));
+            let first = s
+                .allocate_dci(
+                    PdcchAggregation::L2,
+                    &search_spaces[0][tti.to_usize() % 10],
+                    rntis[0],
+                )
+                .unwrap();
+            let saved = s.get_allocs();
+
+            s.checkpoint();
+            for i in 1..3 {
+                s.allocate_dci(
+                    PdcchAggregation::L2,
+                    &search_spaces[i][tti.to_usize() % 10],
+                    rntis[i],
+                )
+                .unwrap();
+            }
+            assert_eq!(s.current_cfi(), Cfi::Four);
+
+            // Nested checkpoint with release rebuilds the state
+            s.checkpoint();
+            s.release_dci(first).unwrap();
+            s.rollback().unwrap();
+            assert_eq!(s.get_allocs().0.len(), 3);
+
+            s.rollback().unwrap();
+            assert_eq!(s.get_allocs(), saved);
+
+            // Committed DCIs stay, DCI index continues after the restored one
+            s.checkpoint();
+            let second = s
+                .allocate_dci(
+                    PdcchAggregation::L2,
+                    &search_spaces[1][tti.to_usize() % 10],
+                    rntis[1],
+                )
+                .unwrap();
+            assert_eq!(second, first + 1);
+            s.commit().unwrap();
+            assert_eq!(s.commit(), Err(AllocationError::InvalidParameter));
+            assert_eq!(s.get_allocs().0.len(), 2);
+        }
+    }
+
+    #[test]
+    fn preemption() {
+        let count_table = count_table(6);
+
+        let tti = TtiPoint::from(1);
+        let dcis = [(Rnti(10249), 1), (Rnti(7386), 0), (Rnti(45236), 2)];
+        for mut s in allocators(count_table) {
+            s.new_tti();
+            for (rnti, priority) in dcis {
+                let search_space = calculate_search_space(&rnti, &count_table);
+                s.allocate_dci_in(&DciRequest {
+                    priority,
+                    ..DciRequest::new(
+                        PdcchAggregation::L2,
+                        &search_space[tti.to_usize() % 10],
+                        rnti,
+                    )
+                })
+                .unwrap();
+            }
+
+            // L4 needs two of the three DCIs, only one has lower priority
+            let rnti = Rnti(18567);
+            let search_space = calculate_search_space(&rnti, &count_table);
+            assert_eq!(
+                s.allocate_dci_preempting(&DciRequest {
+                    priority: 1,
+                    ..DciRequest::new(
+                        PdcchAggregation::L4,
+                        &search_space[tti.to_usize() % 10],
+                        rnti
+                    )
+                }),
+                Err(AllocationError::NoCchSpace)
+            );
+            assert_eq!(s.get_allocs().0.len(), 3);
+            assert_eq!(s.current_cfi(), Cfi::Four);
+
+            let (dci_index, preempted) = s
+                .allocate_dci_preempting(&DciRequest {
+                    priority: 3,
+                    ..DciRequest::new(
+                        PdcchAggregation::L4,
+                        &search_space[tti.to_usize() % 10],
+                        rnti,
+                    )
+                })
+                .unwrap();
+            assert_eq!(dci_index, 3);
+            assert_eq!(preempted.len(), 2);
+
+            let (allocs, mask, _) = s.get_allocs();
+            assert_eq!(allocs.len(), 2);
+            assert!(mask.all());
+            for alloc in allocs.iter() {
+                assert!(!preempted.contains(&alloc.dci_index));
+            }
+            assert!(allocs
+                .iter()
+                .any(|alloc| alloc.dci_index == dci_index && alloc.priority == 3));
+        }
+    }
+
+    #[test]
+    fn preemption_out_of_budget() {
+        let count_table = count_table(25);
+
+        let mut allocators: [Box<dyn PdcchAllocator>; 2] = [
+            Box::new(PdcchSchedTree::new(count_table)),
+            Box::new(PdcchSchedOptimal::new(count_table)),
+        ];
+        let mut rng = StdRng::seed_from_u64(20);
+        let mut failures = 0;
+        for _ in 0..2000 {
+            let budget = Budget {
+                max_steps: Some(rng.gen_range(1..40)),
+                time: None,
+            };
+            let dcis = random_dcis(&mut rng, &count_table, 8);
+            let preempting = DciRequest {
+                priority: 3,
+                ..random_dcis(&mut rng, &count_table, 1).remove(0)
+            };
+            for s in allocators.iter_mut() {
+                s.set_budget(budget);
+                s.new_tti();
+                for dci in dcis.iter() {
+                    let _ = s.allocate_dci_in(dci);
+                }
+                let (allocs, mask, cfi) = s.get_allocs();
+                if s.allocate_dci_preempting(&preempting).is_err() {
+                    // Budget may run out after the DCIs to release are chosen
+                    assert_eq!(s.get_allocs(), (allocs, mask, cfi));
+                    failures += 1;
+                }
+            }
+        }
+        assert!(failures > 0);
+    }
+
+    #[test]
+    fn batch_allocation() {
+        let count_table = count_table(6);
+
+        // Three L2 DCIs have more weight than L4 with one of them
+        let dcis = [
+            dci(PdcchAggregation::L4, Rnti(18567), &count_table, 1),
+            dci(PdcchAggregation::L2, Rnti(10249), &count_table, 1),
+            dci(PdcchAggregation::L2, Rnti(7386), &count_table, 1),
+            dci(PdcchAggregation::L2, Rnti(45236), &count_table, 1),
+        ];
+        for mut s in allocators(count_table) {
+            s.new_tti();
+            let dci_indices = s.allocate_batch(&dcis);
+            assert_eq!(dci_indices[0], None);
+            assert!(dci_indices[1..].iter().all(|dci_index| dci_index.is_some()));
+            assert_eq!(s.get_allocs().0.len(), 3);
+
+            // Priority of L4 outweighs two L2 DCIs
+            let mut dcis = dcis.clone();
+            dcis[0].priority = 2;
+            s.new_tti();
+            let dci_indices = s.allocate_batch(&dcis);
+            assert!(dci_indices[0].is_some());
+            assert_eq!(
+                dci_indices
+                    .iter()
+                    .filter(|dci_index| dci_index.is_some())
+                    .count(),
+                2
+            );
+
+            // Two L2 DCIs need 4 CCEs
+            s.new_tti();
+            let dci_indices = s.allocate_batch(&dcis[1..3]);
+            assert!(dci_indices.iter().all(|dci_index| dci_index.is_some()));
+            assert_eq!(s.current_cfi(), Cfi::Three);
+        }
+    }
+
+    #[test]
+    fn batch_keeps_best_order() {
+        let count_table = count_table(25);
+
+        let mut rng = StdRng::seed_from_u64(9);
+        for start_position in [StartPosition::seeded(3), StartPosition::counter(0)] {
+            for mut s in start_position_allocators(count_table, start_position) {
+                for _ in 0..50 {
+                    let subframe = rng.gen_range(0..10);
+                    let dcis: Vec<DciRequest> = (0..10)
+                        .map(|_| {
+                            let rnti = Rnti(rng.gen_range(1..u16::MAX));
+                            let aggregation_level = PdcchAggregation::list()[rng.gen_range(0..3)];
+                            dci(aggregation_level, rnti, &count_table, subframe)
+                        })
+                        .collect();
+
+                    s.new_tti();
+                    let mut best = None;
+                    for order in BatchOrder::list() {
+                        s.checkpoint();
+                        let dci_indices = s.allocate_batch_in_order(&dcis, order);
+                        let weight: u32 = dcis
+                            .iter()
+                            .zip(dci_indices.iter())
+                            .filter(|(_, dci_index)| dci_index.is_some())
+                            .map(|(dci, _)| dci.weight())
+                            .sum();
+                        let allocs = s.get_allocs();
+                        let score = (weight, Reverse(allocs.2));
+                        s.rollback().unwrap();
+                        if best
+                            .as_ref()
+                            .is_none_or(|(best_score, _, _)| score > *best_score)
+                        {
+                            best = Some((score, dci_indices, allocs));
+                        }
+                    }
+                    let (_, best_indices, best_allocs) = best.unwrap();
+
+                    let dci_indices = s.allocate_batch(&dcis);
+                    assert_eq!(dci_indices, best_indices);
+                    assert_eq!(s.get_allocs(), best_allocs);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn pdsch_cfi() {
+        let count_table = count_table(25);
+        let re_table = resource_allocation::calculate_pdsch_re_table(&cell(25));
+
+        let mut allocators = allocators(count_table);
+        let mut rng = StdRng::seed_from_u64(4);
+        let mut random_dci =
+            |aggregation_level| dci(aggregation_level, Rnti(rng.gen()), &count_table, 1);
+        let small_dcis: Vec<_> = (0..8).map(|_| random_dci(PdcchAggregation::L2)).collect();
+        // 12 PRB at CFI 1, 14 at CFI 2
+        let large_dcis = [
+            random_dci(PdcchAggregation::L4),
+            random_dci(PdcchAggregation::L1),
+            random_dci(PdcchAggregation::L1),
+        ];
+        let payload = |bits| PdschPayload {
+            bits,
+            bits_per_re: 1.0,
+        };
+
+        for s in allocators.iter_mut() {
+            // PDCCH is the limit: CFI 1 holds one DCI of 2 CCEs
+            s.new_tti();
+            let payloads = [payload(100); 8];
+            let (cfi, dci_indices) =
+                s.allocate_batch_with_pdsch(&small_dcis, &payloads, 25, &re_table);
+            assert!(cfi > Cfi::One);
+            assert_eq!(s.get_allocs().2, cfi);
+            let placed = dci_indices.iter().flatten().count();
+            assert!(placed > 1);
+            assert_eq!(s.get_allocs().0.len(), placed);
+
+            // PDSCH is the limit: two DCIs at CFI 1 carry more than one at CFI 2
+            s.new_tti();
+            let payloads = [payload(1728); 3];
+            let (cfi, dci_indices) =
+                s.allocate_batch_with_pdsch(&large_dcis, &payloads, 25, &re_table);
+            assert_eq!(cfi, Cfi::One);
+            assert_eq!(dci_indices[0], None);
+            assert!(dci_indices[1].is_some() && dci_indices[2].is_some());
+            assert_eq!(s.get_allocs().0.len(), 2);
+
+            // PDSCH without spectral efficiency is never scheduled
+            s.new_tti();
+            let payloads = [PdschPayload::default(), payload(100), payload(100)];
+            let (_, dci_indices) =
+                s.allocate_batch_with_pdsch(&large_dcis, &payloads, 25, &re_table);
+            assert_eq!(dci_indices[0], None);
+            assert!(dci_indices[1].is_some() && dci_indices[2].is_some());
+        }
+    }
+
+    #[test]
+    fn aggregation_fallback() {
+        use PdcchAggregation::*;
+
+        let count_table = count_table(25);
+        let mut allocators = allocators(count_table);
+        let rntis = [Rnti(100), Rnti(200)];
+        let search_spaces =
+            rntis.map(|rnti| calculate_search_space(&rnti, &count_table)[1].clone());
+
+        for s in allocators.iter_mut() {
+            // CFI 1 has 3 CCEs, one DCI of level 2 leaves one CCE
+            s.set_cfi_policy(CfiPolicy::Cap(Cfi::One));
+            s.new_tti();
+            s.allocate_dci(L2, &search_spaces[0], rntis[0]).unwrap();
+
+            let choice = AggregationChoice::Levels {
+                preferred: L2,
+                alternatives: BoundedVec::new(),
+            };
+            let result =
+                s.allocate_dci_adaptive(&choice, &DciRequest::new(L1, &search_spaces[1], rntis[1]));
+            assert_eq!(result, Err(AllocationError::NoCchSpace));
+
+            let choice = AggregationChoice::Levels {
+                preferred: L2,
+                alternatives: BoundedVec::try_from(vec![L1]).unwrap(),
+            };
+            let result =
+                s.allocate_dci_adaptive(&choice, &DciRequest::new(L1, &search_spaces[1], rntis[1]));
+            assert_eq!(result, Ok((1, L1)));
+            let (allocs, _, cfi) = s.get_allocs();
+            assert_eq!(cfi, Cfi::One);
+            assert_eq!(allocs[1].aggregation_level, L1);
+        }
+    }
+
+    #[test]
+    fn coding_rate_limit() {
+        use crate::allocator::dci_format::{CodingRateLimit, DciSizes};
+        use PdcchAggregation::*;
+
+        let count_table = count_table(100);
+        let mut allocators = allocators(count_table);
+        let rnti = Rnti(61);
+        let search_space = calculate_search_space(&rnti, &count_table)[1].clone();
+        // Format 2 with CRC has 67 bits, 72 bits of L1 give coding rate 0.93
+        let limit = CodingRateLimit {
+            sizes: DciSizes::new(&cell(100)),
+            max_coding_rate: 0.8,
+        };
+
+        for s in allocators.iter_mut() {
+            s.set_coding_rate_limit(Some(limit));
+            s.new_tti();
+            let result = s.allocate_dci_in(&DciRequest {
+                format: DciFormat::F2,
+                ..DciRequest::new(L1, &search_space, rnti)
+            });
+            assert_eq!(result, Err(AllocationError::CodingRateTooHigh));
+            let result = s.allocate_dci_preempting(&DciRequest {
+                format: DciFormat::F2,
+                priority: 1,
+                ..DciRequest::new(L1, &search_space, rnti)
+            });
+            assert_eq!(result, Err(AllocationError::CodingRateTooHigh));
+            s.allocate_dci_in(&DciRequest {
+                format: DciFormat::F1,
+                ..DciRequest::new(L1, &search_space, rnti)
+            })
+            .unwrap();
+
+            let choice = AggregationChoice::Levels {
+                preferred: L1,
+                alternatives: BoundedVec::try_from(vec![L2]).unwrap(),
+            };
+            let result = s.allocate_dci_adaptive(
+                &choice,
+                &DciRequest {
+                    format: DciFormat::F2,
+                    ..DciRequest::new(L1, &search_space, rnti)
+                },
+            );
+            assert_eq!(result, Ok((1, L2)));
+            let allocs = s.get_allocs().0;
+            assert_eq!(allocs[0].format, DciFormat::F1);
+            assert_eq!(allocs[1].format, DciFormat::F2);
+
+            s.set_coding_rate_limit(None);
+            s.allocate_dci_in(&DciRequest {
+                format: DciFormat::F2,
+                ..DciRequest::new(L1, &search_space, rnti)
+            })
+            .unwrap();
+        }
+    }
+
+    #[test]
+    fn cfi_policy() {
+        let count_table = count_table(25);
+        let mut allocators = allocators(count_table);
+        let mut rng = StdRng::seed_from_u64(9);
+        let mut random_dci = || {
+            let rnti = Rnti(rng.gen());
+            let aggregation_level = PdcchAggregation::list()[rng.gen_range(0..3)];
+            (
+                aggregation_level,
+                calculate_search_space(&rnti, &count_table)[1].clone(),
+                rnti,
+            )
+        };
+        let dcis: Vec<_> = (0..12).map(|_| random_dci()).collect();
+        let light_dci = random_dci();
+
+        for s in allocators.iter_mut() {
+            s.set_cfi_policy(CfiPolicy::Fixed(Cfi::Three));
+            s.new_tti();
+            let (aggregation_level, search_space, rnti) = &dcis[0];
+            s.allocate_dci(*aggregation_level, search_space, *rnti)
+                .unwrap();
+            assert_eq!(s.current_cfi(), Cfi::Three);
+            assert_eq!(s.get_allocs().2, Cfi::Three);
+
+            s.set_cfi_policy(CfiPolicy::Cap(Cfi::One));
+            s.new_tti();
+            for (aggregation_level, search_space, rnti) in dcis.iter() {
+                let _ = s.allocate_dci(*aggregation_level, search_space, *rnti);
+                assert_eq!(s.current_cfi(), Cfi::One);
+            }
+
+            // Released DCIs made room at a lower CFI
+            s.set_cfi_policy(CfiPolicy::Minimize);
+            s.new_tti();
+            let mut search_spaces = Vec::new();
+            for (aggregation_level, search_space, rnti) in dcis.iter() {
+                if s.allocate_dci(*aggregation_level, search_space, *rnti)
+                    .is_ok()
+                {
+                    search_spaces.push(search_space);
+                }
+            }
+            let heavy_cfi = s.current_cfi();
+            assert!(heavy_cfi > Cfi::One);
+            for dci_index in 1..search_spaces.len() as u8 {
+                s.release_dci(dci_index).unwrap();
+            }
+            let (allocs, total_mask, cfi) = s.get_allocs();
+            let (aggregation_level, search_space, _) = &dcis[0];
+            let lowest_cfi = Cfi::list()
+                .into_iter()
+                .find(|cfi| !search_space[cfi.index()][aggregation_level.index()].is_empty())
+                .unwrap();
+            assert!(lowest_cfi < heavy_cfi);
+            assert_eq!(cfi, lowest_cfi);
+            assert_eq!(total_mask.size(), count_table[cfi.index()] as usize);
+            let alloc = &allocs[0];
+            let candidates = &search_spaces[0][cfi.index()][alloc.aggregation_level.index()];
+            assert!(candidates.contains(&alloc.start_cce));
+
+            // Next TTI starts from the CFI of the last one
+            s.set_cfi_policy(CfiPolicy::LastTti);
+            s.new_tti();
+            for (aggregation_level, search_space, rnti) in dcis.iter() {
+                let _ = s.allocate_dci(*aggregation_level, search_space, *rnti);
+            }
+            let last_cfi = s.get_allocs().2;
+            s.new_tti();
+            assert_eq!(s.current_cfi(), last_cfi);
+            let (aggregation_level, search_space, rnti) = &light_dci;
+            s.allocate_dci(*aggregation_level, search_space, *rnti)
+                .unwrap();
+            assert_eq!(s.get_allocs().2, Cfi::One);
+        }
+    }
+
+    #[test]
+    fn budget() {
+        let count_table = count_table(100);
+
+        let displacement = Displacement {
+            depth: 3,
+            ..Default::default()
+        };
+        let mut allocators: [Box<dyn PdcchAllocator>; 3] = [
+            Box::new(PdcchSchedTree::new(count_table)),
+            Box::new(PdcchSchedShuffling::with_displacement(
+                count_table,
+                StartPosition::counter(0),
+                displacement,
+            )),
+            Box::new(PdcchSchedOptimal::new(count_table)),
+        ];
+        let budget = Budget {
+            max_steps: Some(100),
+            time: None,
+        };
+        for s in allocators.iter_mut() {
+            s.set_budget(budget);
+        }
+
+        let mut rng = StdRng::seed_from_u64(8);
+        let mut exhaustive = [0; 3];
+        for _ in 0..20 {
+            let subframe = rng.gen_range(0..10);
+            let dcis: Vec<_> = (0..MAX_PDCCH)
+                .map(|_| {
+                    let rnti = Rnti(rng.gen());
+                    let aggregation_level = PdcchAggregation::list()[rng.gen_range(0..2)];
+                    (
+                        aggregation_level,
+                        calculate_search_space(&rnti, &count_table),
+                        rnti,
+                    )
+                })
+                .collect();
+            for (k, s) in allocators.iter_mut().enumerate() {
+                s.new_tti();
+                let mut allocated = 0;
+                for (aggregation_level, search_space, rnti) in dcis.iter() {
+                    if allocated == 4 {
+                        s.checkpoint();
+                    }
+                    if s.allocate_dci(*aggregation_level, &search_space[subframe], *rnti)
+                        .is_ok()
+                    {
+                        allocated += 1;
+                    }
+                }
+                // Out of budget the allocator still places DCIs into free candidates
+                assert!(allocated >= 8);
+                let _ = s.release_dci(0);
+                s.rollback().unwrap();
+
+                check_allocs(s.as_ref());
+                if s.exhaustive() {
+                    exhaustive[k] += 1;
+                }
+            }
+        }
+        // Tree of 16 DCIs at 100 PRB does not fit into 100 nodes
+        assert_eq!(exhaustive[0], 0);
+
+        // Small load fits into the budget
+        let tree = &mut allocators[0];
+        tree.new_tti();
+        let rnti = Rnti(rng.gen());
+        let search_space = calculate_search_space(&rnti, &count_table);
+        tree.allocate_dci(PdcchAggregation::L4, &search_space[0], rnti)
+            .unwrap();
+        assert!(tree.exhaustive());
+    }
+}
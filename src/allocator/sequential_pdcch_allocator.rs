@@ -1,90 +1,113 @@
-use crate::defines;
 use crate::signalling::structs::{AllocationError, AllocationResult};
-use crate::types::bitset::BoundedBitset;
 use crate::types::bounded_vec::BoundedVec;
 use crate::types::cfi::Cfi;
-use crate::types::rnti::Rnti;
-use rand::Rng;
-use std::array;
-
-use structures;
 
+use super::dci_format::CodingRateLimit;
+use super::pdcch_allocator::{CfiBuffer, CfiState, PdcchAllocator};
+use super::structures::{
+    Budget, BudgetCounter, CceCountTable, CceMask, CfiPolicy, CfiSearchSpace, DciRequest,
+    PdcchAllocation, SfSearchSpace, StartPosition, MAX_PDCCH,
+};
 
 #[derive(Clone, Debug)]
 pub struct PdcchSchedSequential {
-    pub current_cfi: Cfi,
-    dci_index: u8,
+    state: CfiState<SequentialAllocation>,
 
-    allocation_buffer: [SequentialAllocation; defines::NUM_OF_CFI],
+    start_position: StartPosition,
+    /// Start position at every checkpoint, restored so that DCIs placed again after
+    /// `rollback` get the same candidates
+    start_checkpoints: Vec<StartPosition>,
 }
 
 impl PdcchSchedSequential {
     pub fn new(cce_table: CceCountTable) -> PdcchSchedSequential {
-        PdcchSchedSequential {
-            current_cfi: Default::default(),
-            dci_index: 0,
+        Self::with_start_position(cce_table, StartPosition::Random)
+    }
 
-            allocation_buffer: array::from_fn(|i| SequentialAllocation::new(cce_table[i])),
+    /// Allocator with the given choice of the first candidate, use `StartPosition::Seeded`
+    /// or `StartPosition::Counter` for reproducible allocations
+    pub fn with_start_position(
+        cce_table: CceCountTable,
+        start_position: StartPosition,
+    ) -> PdcchSchedSequential {
+        PdcchSchedSequential {
+            state: CfiState::new(cce_table, SequentialAllocation::new),
+            start_position: start_position.restorable(),
+            start_checkpoints: Vec::new(),
         }
     }
+}
 
-    pub fn new_tti(&mut self) {
-        self.current_cfi = Cfi::One;
-        self.dci_index = 0;
-        for alloc_buf in self.allocation_buffer.iter_mut() {
-            alloc_buf.reset();
-        }
+impl PdcchAllocator for PdcchSchedSequential {
+    fn new_tti(&mut self) {
+        self.state.new_tti();
+        self.start_position.new_tti();
+        self.start_checkpoints.clear();
     }
 
-    pub fn increment_cfi(&mut self) -> AllocationResult {
-        //        println!("incrementing cfi");
-        match self.current_cfi {
-            Cfi::One => {
-                self.current_cfi = Cfi::Two;
-                Ok(())
-            }
-            Cfi::Two => {
-                self.current_cfi = Cfi::Three;
-                Ok(())
-            }
-            Cfi::Three => Err(AllocationError::NoCchSpace),
-        }
+    fn current_cfi(&self) -> Cfi {
+        self.state.current_cfi
     }
 
-    pub fn allocate_dci(
-        &mut self,
-        aggregation_level: PdcchAggregation,
-        search_space: &SfSearchSpace,
-        rnti: Rnti,
-    ) -> Result<u8, AllocationError> {
-        let start_cfi = self.current_cfi;
-        for cfi in start_cfi.iter() {
-            let res = self.allocation_buffer[cfi.index()].try_alloc(
-                aggregation_level,
-                &search_space[cfi.index()],
-                rnti,
-            );
-            match res {
-                Ok(()) => (),
-                Err(()) => {
-                    self.increment_cfi()?;
-                    continue;
-                }
-            }
-        }
+    fn increment_cfi(&mut self) -> AllocationResult {
+        self.state.increment_cfi()
+    }
 
-        self.dci_index += 1;
-        Ok(self.dci_index - 1)
+    fn allocate_dci_in(&mut self, request: &DciRequest) -> Result<u8, AllocationError> {
+        let start_offset = self.start_position.next_offset();
+        self.state
+            .allocate(request, |buffer, dci, search_space, _| {
+                buffer.try_alloc(dci, search_space, start_offset)
+            })
     }
 
-    pub fn get_allocs(&self) -> (BoundedVec<PdcchAllocation, MAX_PDCCH>, CceMask, Cfi) {
-        let (allocation_buffer, total_mask) =
-            self.allocation_buffer[self.current_cfi.index()].get_allocs();
-        (allocation_buffer, total_mask, self.current_cfi)
+    fn release_dci(&mut self, dci_index: u8) -> AllocationResult {
+        self.state.release_dci(dci_index)
+    }
+
+    fn checkpoint(&mut self) {
+        self.state.checkpoint();
+        self.start_checkpoints.push(self.start_position.clone());
+    }
+
+    fn commit(&mut self) -> AllocationResult {
+        self.state.commit()?;
+        self.start_checkpoints.pop();
+        Ok(())
+    }
+
+    fn rollback(&mut self) -> AllocationResult {
+        self.state.rollback()?;
+        self.start_position = self.start_checkpoints.pop().unwrap();
+        Ok(())
+    }
+
+    fn get_allocs(&self) -> (BoundedVec<PdcchAllocation, MAX_PDCCH>, CceMask, Cfi) {
+        self.state.get_allocs()
+    }
+
+    fn common_search_space(&self) -> &SfSearchSpace {
+        &self.state.common_search_space
     }
-}
 
-pub type CceMask = BoundedBitset<{ defines::NUM_OF_CCES }>;
+    fn set_cfi_policy(&mut self, cfi_policy: CfiPolicy) {
+        self.state.set_cfi_policy(cfi_policy);
+    }
+
+    fn set_coding_rate_limit(&mut self, coding_rate_limit: Option<CodingRateLimit>) {
+        self.state.set_coding_rate_limit(coding_rate_limit);
+    }
+
+    /// Sequential placement does not search, the budget limits only placing DCIs at a lower
+    /// CFI in `get_allocs`
+    fn set_budget(&mut self, budget: Budget) {
+        self.state.budget.set_budget(budget);
+    }
+
+    fn exhaustive(&self) -> bool {
+        self.state.budget.exhaustive()
+    }
+}
 
 #[derive(Clone, Debug)]
 struct SequentialAllocation {
@@ -104,22 +127,17 @@ impl SequentialAllocation {
         }
     }
 
-    fn reset(&mut self) {
-        self.allocation_buffer.clear();
-        self.total_mask = CceMask::new(self.cce_count as usize);
-    }
-
     fn try_alloc(
         &mut self,
-        aggregation_level: PdcchAggregation,
+        dci: &PdcchAllocation,
         search_space: &CfiSearchSpace,
-        rnti: Rnti,
+        start_offset: usize,
     ) -> Result<(), ()> {
+        let aggregation_level = dci.aggregation_level;
         let search_space_len = search_space[aggregation_level as usize].len();
 
         if search_space_len != 0 {
-
-            let start_cce_idx = rand::thread_rng().gen_range(0..search_space_len);
+            let start_cce_idx = start_offset % search_space_len;
 
             for cce_idx in start_cce_idx..(start_cce_idx + search_space_len) {
                 let mut alloc_mask = CceMask::new(self.cce_count as usize);
@@ -131,30 +149,112 @@ impl SequentialAllocation {
                 if (alloc_mask & self.total_mask).any() {
                     continue;
                 } else {
-                    let start_cce = search_space[aggregation_level as usize][cce_idx % search_space_len];
+                    let start_cce =
+                        search_space[aggregation_level as usize][cce_idx % search_space_len];
                     let alloc = PdcchAllocation {
-                        aggregation_level,
                         start_cce,
-                        rnti,
+                        mask: alloc_mask,
+                        ..dci.clone()
                     };
-                    self.total_mask = alloc_mask | self.total_mask;
                     self.allocation_buffer.push(alloc).map_err(|_| ())?;
+                    self.total_mask = alloc_mask | self.total_mask;
                     return Ok(());
                 }
             }
         }
         Err(())
     }
+}
+
+impl CfiBuffer for SequentialAllocation {
+    /// Buffer holds at most MAX_PDCCH DCIs, so it is copied
+    type Checkpoint = Self;
+
+    fn reset(&mut self) {
+        self.allocation_buffer.clear();
+        self.total_mask = CceMask::new(self.cce_count as usize);
+    }
+
+    fn dci_count(&self) -> usize {
+        self.allocation_buffer.len()
+    }
+
+    fn contains(&self, dci_index: u8) -> bool {
+        self.allocation_buffer
+            .iter()
+            .any(|alloc| alloc.dci_index == dci_index)
+    }
+
+    fn release(&mut self, dci_index: u8, _budget: &mut BudgetCounter) {
+        if let Some(idx) = self
+            .allocation_buffer
+            .iter()
+            .position(|alloc| alloc.dci_index == dci_index)
+        {
+            self.allocation_buffer.remove(idx);
+            self.total_mask = CceMask::new(self.cce_count as usize);
+            for alloc in self.allocation_buffer.iter() {
+                self.total_mask |= alloc.mask;
+            }
+        }
+    }
+
+    fn checkpoint(&mut self) -> Self {
+        self.clone()
+    }
+
+    fn restore(&mut self, checkpoint: Self) {
+        *self = checkpoint;
+    }
 
     fn get_allocs(&self) -> (BoundedVec<PdcchAllocation, MAX_PDCCH>, CceMask) {
         (self.allocation_buffer.clone(), self.total_mask)
     }
 }
 
-#[derive(Clone, Debug, Default)]
-pub struct PdcchAllocation {
-    // Location data
-    pub aggregation_level: PdcchAggregation,
-    pub start_cce: u8,
-    pub rnti: Rnti,
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::allocator::structures::{calculate_search_space, PdcchAggregation};
+    use crate::allocator::test_fixture::{count_table, start_position_allocators};
+    use crate::types::rnti::Rnti;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    #[test]
+    fn reproducible_start_position() {
+        let count_table = count_table(25);
+
+        for start_position in [StartPosition::seeded(17), StartPosition::counter(0)] {
+            let mut first = start_position_allocators(count_table, start_position.clone());
+            let mut second = start_position_allocators(count_table, start_position);
+
+            let mut rng = StdRng::seed_from_u64(6);
+            for _ in 0..100 {
+                let subframe = rng.gen_range(0..10);
+                let dcis: Vec<_> = (0..7)
+                    .map(|_| {
+                        let rnti = Rnti(rng.gen());
+                        let aggregation_level = PdcchAggregation::list()[rng.gen_range(0..3)];
+                        (
+                            aggregation_level,
+                            calculate_search_space(&rnti, &count_table),
+                            rnti,
+                        )
+                    })
+                    .collect();
+                for (s1, s2) in first.iter_mut().zip(second.iter_mut()) {
+                    s1.new_tti();
+                    s2.new_tti();
+                    for (aggregation_level, search_space, rnti) in dcis.iter() {
+                        assert_eq!(
+                            s1.allocate_dci(*aggregation_level, &search_space[subframe], *rnti),
+                            s2.allocate_dci(*aggregation_level, &search_space[subframe], *rnti)
+                        );
+                    }
+                    assert_eq!(s1.get_allocs(), s2.get_allocs());
+                }
+            }
+        }
+    }
 }
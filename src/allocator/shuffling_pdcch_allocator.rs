@@ -1,88 +1,149 @@
-use crate::defines;
 use crate::signalling::structs::{AllocationError, AllocationResult};
-use crate::types::bitset::BoundedBitset;
 use crate::types::bounded_vec::BoundedVec;
 use crate::types::cfi::Cfi;
-use crate::types::rnti::Rnti;
-use rand::Rng;
-use std::array;
 
-use structures;
+use super::dci_format::CodingRateLimit;
+use super::pdcch_allocator::{CfiBuffer, CfiState, PdcchAllocator};
+use super::structures::{
+    Budget, BudgetCounter, CceCountTable, CceMask, CfiPolicy, CfiSearchSpace, DciRequest,
+    PdcchAllocation, SfSearchSpace, StartPosition, MAX_PDCCH,
+};
 
 #[derive(Clone, Debug)]
 pub struct PdcchSchedShuffling {
-    pub current_cfi: Cfi,
-    dci_index: u8,
+    state: CfiState<ShufflingAllocation>,
 
-    allocation_buffer: [ShufflingAllocation; defines::NUM_OF_CFI],
+    start_position: StartPosition,
+    /// Start position at every checkpoint, see `PdcchSchedSequential`
+    start_checkpoints: Vec<StartPosition>,
+}
+
+/// Limits of moving allocated DCIs to make room for a new one
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Displacement {
+    /// Length of a chain of moves: 1 moves only the DCIs blocking the new one, 2 also moves
+    /// the DCIs blocking their new positions and so on
+    pub depth: u8,
+    /// Candidate positions tried by one shuffle at most
+    pub max_work: usize,
+}
+
+impl Default for Displacement {
+    fn default() -> Self {
+        Displacement {
+            depth: 1,
+            max_work: 256,
+        }
+    }
 }
 
 impl PdcchSchedShuffling {
     pub fn new(cce_table: CceCountTable) -> Self {
+        Self::with_start_position(cce_table, StartPosition::Random)
+    }
+
+    /// Allocator with the given choice of the first candidate, see `PdcchSchedSequential`
+    pub fn with_start_position(cce_table: CceCountTable, start_position: StartPosition) -> Self {
+        Self::with_displacement(cce_table, start_position, Displacement::default())
+    }
+
+    /// Allocator which moves chains of up to `displacement.depth` DCIs
+    pub fn with_displacement(
+        cce_table: CceCountTable,
+        start_position: StartPosition,
+        displacement: Displacement,
+    ) -> Self {
         PdcchSchedShuffling {
-            current_cfi: Default::default(),
-            dci_index: 0,
-            allocation_buffer: array::from_fn(|i| ShufflingAllocation::new(cce_table[i])),
+            state: CfiState::new(cce_table, |cce_count| {
+                ShufflingAllocation::new(cce_count, displacement)
+            }),
+            start_position: start_position.restorable(),
+            start_checkpoints: Vec::new(),
         }
     }
 
-    pub fn new_tti(&mut self) {
-        self.current_cfi = Cfi::One;
-        self.dci_index = 0;
-        for alloc_buf in self.allocation_buffer.iter_mut() {
-            alloc_buf.reset();
-        }
+    /// Buffers of all CFIs are consistent, checked after every change
+    fn is_consistent(&self) -> bool {
+        let buffers = &self.state.buffers;
+        buffers.iter().all(ShufflingAllocation::is_consistent)
     }
+}
 
-    pub fn increment_cfi(&mut self) -> AllocationResult {
-        //        println!("incrementing cfi");
-        match self.current_cfi {
-            Cfi::One => {
-                self.current_cfi = Cfi::Two;
-                Ok(())
-            }
-            Cfi::Two => {
-                self.current_cfi = Cfi::Three;
-                Ok(())
-            }
-            Cfi::Three => Err(AllocationError::NoCchSpace),
-        }
+impl PdcchAllocator for PdcchSchedShuffling {
+    fn new_tti(&mut self) {
+        self.state.new_tti();
+        self.start_position.new_tti();
+        self.start_checkpoints.clear();
     }
 
-    pub fn allocate_dci(
-        &mut self,
-        aggregation_level: PdcchAggregation,
-        search_space: &SfSearchSpace,
-        rnti: Rnti,
-    ) -> Result<u8, AllocationError> {
-        let start_cfi = self.current_cfi;
-        for cfi in start_cfi.iter() {
-            let res = self.allocation_buffer[cfi.index()].try_alloc(
-                aggregation_level,
-                &search_space[cfi.index()],
-                rnti,
-            );
-            match res {
-                Ok(()) => (),
-                Err(()) => {
-                    self.increment_cfi()?;
-                    continue;
-                }
-            }
-        }
+    fn current_cfi(&self) -> Cfi {
+        self.state.current_cfi
+    }
 
-        self.dci_index += 1;
-        Ok(self.dci_index - 1)
+    fn increment_cfi(&mut self) -> AllocationResult {
+        self.state.increment_cfi()
     }
 
-    pub fn get_allocs(&self) -> (BoundedVec<PdcchAllocation, MAX_PDCCH>, CceMask, Cfi) {
-        let (allocation_buffer, total_mask) =
-            self.allocation_buffer[self.current_cfi.index()].get_allocs();
-        (allocation_buffer, total_mask, self.current_cfi)
+    fn allocate_dci_in(&mut self, request: &DciRequest) -> Result<u8, AllocationError> {
+        let start_offset = self.start_position.next_offset();
+        let res = self
+            .state
+            .allocate(request, |buffer, dci, search_space, budget| {
+                buffer.try_alloc(dci, search_space, start_offset, budget)
+            });
+        debug_assert!(self.is_consistent());
+        res
+    }
+
+    fn release_dci(&mut self, dci_index: u8) -> AllocationResult {
+        self.state.release_dci(dci_index)?;
+        debug_assert!(self.is_consistent());
+        Ok(())
     }
-}
 
-pub type CceMask = BoundedBitset<{ defines::NUM_OF_CCES }>;
+    fn checkpoint(&mut self) {
+        self.state.checkpoint();
+        self.start_checkpoints.push(self.start_position.clone());
+    }
+
+    fn commit(&mut self) -> AllocationResult {
+        self.state.commit()?;
+        self.start_checkpoints.pop();
+        debug_assert!(self.is_consistent());
+        Ok(())
+    }
+
+    fn rollback(&mut self) -> AllocationResult {
+        self.state.rollback()?;
+        self.start_position = self.start_checkpoints.pop().unwrap();
+        debug_assert!(self.is_consistent());
+        Ok(())
+    }
+
+    fn get_allocs(&self) -> (BoundedVec<PdcchAllocation, MAX_PDCCH>, CceMask, Cfi) {
+        self.state.get_allocs()
+    }
+
+    fn common_search_space(&self) -> &SfSearchSpace {
+        &self.state.common_search_space
+    }
+
+    fn set_cfi_policy(&mut self, cfi_policy: CfiPolicy) {
+        self.state.set_cfi_policy(cfi_policy);
+    }
+
+    fn set_coding_rate_limit(&mut self, coding_rate_limit: Option<CodingRateLimit>) {
+        self.state.set_coding_rate_limit(coding_rate_limit);
+    }
+
+    fn set_budget(&mut self, budget: Budget) {
+        self.state.budget.set_budget(budget);
+    }
+
+    fn exhaustive(&self) -> bool {
+        self.state.budget.exhaustive()
+    }
+}
 
 #[derive(Clone, Debug)]
 struct ShufflingAllocation {
@@ -92,35 +153,48 @@ struct ShufflingAllocation {
     allocation_buffer: BoundedVec<PdcchAllocation, 16>,
     search_space_buffer: BoundedVec<CfiSearchSpace, 16>,
     total_mask: CceMask,
+
+    displacement: Displacement,
+}
+
+/// Positions of the allocated DCIs during a chain of moves. Allocations are changed only
+/// when the whole chain succeeds
+struct MoveChain {
+    start_cces: Vec<u8>,
+    masks: Vec<CceMask>,
+    /// Every DCI moves at most once in a shuffle, so chains can not cycle
+    moved: Vec<bool>,
+    /// CCEs of the new DCI and of the moved ones
+    reserved: CceMask,
+    work: usize,
 }
 
 impl ShufflingAllocation {
-    fn new(cce_count: u8) -> ShufflingAllocation {
+    fn new(cce_count: u8, displacement: Displacement) -> ShufflingAllocation {
         ShufflingAllocation {
             cce_count,
             allocation_buffer: BoundedVec::new(),
             search_space_buffer: BoundedVec::new(),
             total_mask: CceMask::new(cce_count as usize),
+            displacement,
         }
     }
 
-    fn reset(&mut self) {
-        self.allocation_buffer.clear();
-        self.search_space_buffer.clear();
-        self.total_mask = CceMask::new(self.cce_count as usize);
-    }
-
     fn try_alloc(
         &mut self,
-        aggregation_level: PdcchAggregation,
+        dci: &PdcchAllocation,
         search_space: &CfiSearchSpace,
-        rnti: Rnti,
+        start_offset: usize,
+        budget: &mut BudgetCounter,
     ) -> Result<(), ()> {
+        if self.allocation_buffer.len() == MAX_PDCCH {
+            return Err(());
+        }
+        let aggregation_level = dci.aggregation_level;
         let search_space_len = search_space[aggregation_level as usize].len();
 
         if search_space_len != 0 {
-
-            let start_cce_idx = rand::thread_rng().gen_range(0..search_space_len);
+            let start_cce_idx = start_offset % search_space_len;
 
             for cce_idx in start_cce_idx..(start_cce_idx + search_space_len) {
                 let mut alloc_mask = CceMask::new(self.cce_count as usize);
@@ -132,88 +206,327 @@ impl ShufflingAllocation {
                 if (alloc_mask & self.total_mask).any() {
                     continue;
                 } else {
-                    let start_cce = search_space[aggregation_level as usize][cce_idx % search_space_len];
+                    let start_cce =
+                        search_space[aggregation_level as usize][cce_idx % search_space_len];
                     let alloc = PdcchAllocation {
-                        aggregation_level,
                         start_cce,
-                        rnti,
                         mask: alloc_mask,
+                        ..dci.clone()
                     };
-                    self.total_mask = alloc_mask | self.total_mask;
                     self.allocation_buffer.push(alloc).map_err(|_| ())?;
+                    self.total_mask = alloc_mask | self.total_mask;
                     self.search_space_buffer
-                    .push(search_space.clone())
-                    .map_err(|_| ())?;
+                        .push(search_space.clone())
+                        .map_err(|_| ())?;
                     return Ok(());
                 }
             }
         }
-        self.shuffle(aggregation_level, search_space, rnti)
+        self.shuffle(dci, search_space, budget)
     }
 
+    /// Places the DCI moving chains of blocking DCIs, state is unchanged on failure.
+    /// Out of budget only free candidates are used
     fn shuffle(
         &mut self,
-        aggregation_level: PdcchAggregation,
+        dci: &PdcchAllocation,
         search_space: &CfiSearchSpace,
-        rnti: Rnti,
+        budget: &mut BudgetCounter,
     ) -> Result<(), ()> {
+        if budget.is_exhausted() {
+            return Err(());
+        }
+        let aggregation_level = dci.aggregation_level;
+        let mut work = 0;
         for &start_cce in search_space[aggregation_level as usize].iter() {
+            let mut chain = MoveChain {
+                start_cces: self
+                    .allocation_buffer
+                    .iter()
+                    .map(|alloc| alloc.start_cce)
+                    .collect(),
+                masks: self
+                    .allocation_buffer
+                    .iter()
+                    .map(|alloc| alloc.mask)
+                    .collect(),
+                moved: vec![false; self.allocation_buffer.len()],
+                reserved: CceMask::new(self.cce_count as usize),
+                work,
+            };
             let mut alloc_mask = CceMask::new(self.cce_count as usize);
             alloc_mask.fill(start_cce as usize, aggregation_level.size(), true)?;
-            let mut flag = false;
-            for idx in 0..self.allocation_buffer.len() {
-                if (alloc_mask & self.allocation_buffer[idx].mask).any() {
-                    for &some_cce in self.search_space_buffer[idx][self.allocation_buffer[idx].aggregation_level as usize].iter() {
-                        let mut temporary_mask = CceMask::new(self.cce_count as usize);
-                        temporary_mask.fill(some_cce as usize, self.allocation_buffer[idx].aggregation_level.size(), true)?;
-
-                        if (temporary_mask & alloc_mask).any() | (temporary_mask & self.total_mask).any() {
-                            flag = false;
-                            continue;
-                        } else {
-                            self.allocation_buffer[idx].mask = temporary_mask;
-                            self.allocation_buffer[idx].start_cce = some_cce;
-                            self.total_mask = CceMask::new(self.cce_count as usize);
-                            for i in 0..self.allocation_buffer.len() {
-                                self.total_mask = self.total_mask | self.allocation_buffer[i].mask;
-                            }
-                            flag = true;
-                            break;
-                        }
-                    }
-                } else {
-                    continue;
+            let placed = self.place(&mut chain, alloc_mask, self.displacement.depth, budget)?;
+            work = chain.work;
+            if placed {
+                for (idx, alloc) in self.allocation_buffer.iter_mut().enumerate() {
+                    alloc.start_cce = chain.start_cces[idx];
+                    alloc.mask = chain.masks[idx];
                 }
-            }
-            if flag {
                 let alloc = PdcchAllocation {
-                    aggregation_level,
                     start_cce,
-                    rnti,
                     mask: alloc_mask,
+                    ..dci.clone()
                 };
-                self.total_mask = alloc_mask | self.total_mask;
                 self.allocation_buffer.push(alloc).map_err(|_| ())?;
                 self.search_space_buffer
-                .push(search_space.clone())
-                .map_err(|_| ())?;
+                    .push(search_space.clone())
+                    .map_err(|_| ())?;
+                self.total_mask = CceMask::new(self.cce_count as usize);
+                for alloc in self.allocation_buffer.iter() {
+                    self.total_mask |= alloc.mask;
+                }
                 return Ok(());
             }
+            if work >= self.displacement.max_work || budget.is_exhausted() {
+                break;
+            }
         }
-
         Err(())
     }
 
+    /// Reserves `mask` and moves the DCIs it overlaps, each of them may start a chain of
+    /// `depth - 1` further moves. On failure the chain is restored
+    fn place(
+        &self,
+        chain: &mut MoveChain,
+        mask: CceMask,
+        depth: u8,
+        budget: &mut BudgetCounter,
+    ) -> Result<bool, ()> {
+        if (mask & chain.reserved).any() {
+            return Ok(false);
+        }
+        let blockers: Vec<usize> = (0..chain.masks.len())
+            .filter(|&idx| !chain.moved[idx] && (chain.masks[idx] & mask).any())
+            .collect();
+        if blockers.is_empty() {
+            chain.reserved |= mask;
+            return Ok(true);
+        }
+        if depth == 0 {
+            return Ok(false);
+        }
+
+        let (start_cces, masks, moved, reserved) = (
+            chain.start_cces.clone(),
+            chain.masks.clone(),
+            chain.moved.clone(),
+            chain.reserved,
+        );
+        chain.reserved |= mask;
+        for idx in blockers {
+            // Already moved out of the way by the chain of a previous blocker
+            if chain.moved[idx] {
+                continue;
+            }
+            chain.moved[idx] = true;
+            let aggregation_level = self.allocation_buffer[idx].aggregation_level;
+            let mut found = false;
+            for &some_cce in self.search_space_buffer[idx][aggregation_level as usize].iter() {
+                if chain.work >= self.displacement.max_work {
+                    budget.truncate();
+                    break;
+                }
+                if !budget.step() {
+                    break;
+                }
+                chain.work += 1;
+                let mut temporary_mask = CceMask::new(self.cce_count as usize);
+                temporary_mask.fill(some_cce as usize, aggregation_level.size(), true)?;
+                if self.place(chain, temporary_mask, depth - 1, budget)? {
+                    chain.start_cces[idx] = some_cce;
+                    chain.masks[idx] = temporary_mask;
+                    found = true;
+                    break;
+                }
+            }
+            if !found {
+                chain.start_cces = start_cces;
+                chain.masks = masks;
+                chain.moved = moved;
+                chain.reserved = reserved;
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Allocations do not overlap and `total_mask` is their union
+    fn is_consistent(&self) -> bool {
+        let mut union = CceMask::new(self.cce_count as usize);
+        for alloc in self.allocation_buffer.iter() {
+            if (union & alloc.mask).any() {
+                return false;
+            }
+            union |= alloc.mask;
+        }
+        union == self.total_mask && self.search_space_buffer.len() == self.allocation_buffer.len()
+    }
+}
+
+impl CfiBuffer for ShufflingAllocation {
+    /// Buffer holds at most MAX_PDCCH DCIs, so it is copied
+    type Checkpoint = Self;
+
+    fn reset(&mut self) {
+        self.allocation_buffer.clear();
+        self.search_space_buffer.clear();
+        self.total_mask = CceMask::new(self.cce_count as usize);
+    }
+
+    fn dci_count(&self) -> usize {
+        self.allocation_buffer.len()
+    }
+
+    fn contains(&self, dci_index: u8) -> bool {
+        self.allocation_buffer
+            .iter()
+            .any(|alloc| alloc.dci_index == dci_index)
+    }
+
+    fn release(&mut self, dci_index: u8, _budget: &mut BudgetCounter) {
+        if let Some(idx) = self
+            .allocation_buffer
+            .iter()
+            .position(|alloc| alloc.dci_index == dci_index)
+        {
+            self.allocation_buffer.remove(idx);
+            self.search_space_buffer.remove(idx);
+            self.total_mask = CceMask::new(self.cce_count as usize);
+            for alloc in self.allocation_buffer.iter() {
+                self.total_mask |= alloc.mask;
+            }
+        }
+    }
+
+    fn checkpoint(&mut self) -> Self {
+        self.clone()
+    }
+
+    fn restore(&mut self, checkpoint: Self) {
+        *self = checkpoint;
+    }
+
     fn get_allocs(&self) -> (BoundedVec<PdcchAllocation, MAX_PDCCH>, CceMask) {
         (self.allocation_buffer.clone(), self.total_mask)
     }
 }
 
-#[derive(Clone, Debug, Default)]
-pub struct PdcchAllocation {
-    // Location data
-    pub aggregation_level: PdcchAggregation,
-    pub start_cce: u8,
-    pub rnti: Rnti,
-    mask: CceMask,
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::allocator::structures::calculate_search_space;
+    use crate::allocator::structures::PdcchAggregation::{self, L1, L4};
+    use crate::allocator::test_fixture::count_table;
+    use crate::types::rnti::Rnti;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    /// Search space with `positions` for one aggregation level
+    fn search_space(aggregation_level: PdcchAggregation, positions: &[u8]) -> CfiSearchSpace {
+        let mut search_space = CfiSearchSpace::default();
+        search_space[aggregation_level.index()] = BoundedVec::try_from(positions.to_vec()).unwrap();
+        search_space
+    }
+
+    fn dci(aggregation_level: PdcchAggregation, dci_index: u8) -> PdcchAllocation {
+        PdcchAllocation {
+            aggregation_level,
+            dci_index,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn shuffle_chains() {
+        let count_table = count_table(25);
+
+        let mut shallow =
+            PdcchSchedShuffling::with_start_position(count_table, StartPosition::counter(0));
+        let displacement = Displacement {
+            depth: 3,
+            ..Default::default()
+        };
+        let mut deep = PdcchSchedShuffling::with_displacement(
+            count_table,
+            StartPosition::counter(0),
+            displacement,
+        );
+
+        let mut rng = StdRng::seed_from_u64(7);
+        let (mut shallow_count, mut deep_count) = (0, 0);
+        for _ in 0..200 {
+            let subframe = rng.gen_range(0..10);
+            shallow.new_tti();
+            deep.new_tti();
+            let mut search_spaces = Vec::new();
+            for _ in 0..10 {
+                let rnti = Rnti(rng.gen());
+                let aggregation_level = PdcchAggregation::list()[rng.gen_range(0..3)];
+                let search_space = calculate_search_space(&rnti, &count_table)[subframe].clone();
+                if shallow
+                    .allocate_dci(aggregation_level, &search_space, rnti)
+                    .is_ok()
+                {
+                    shallow_count += 1;
+                }
+                if deep
+                    .allocate_dci(aggregation_level, &search_space, rnti)
+                    .is_ok()
+                {
+                    deep_count += 1;
+                    search_spaces.push(search_space);
+                }
+            }
+
+            // Moved DCIs stay in their search spaces and do not overlap
+            let (allocs, total_mask, cfi) = deep.get_allocs();
+            let mut union = CceMask::new(total_mask.size());
+            for alloc in allocs.iter() {
+                let candidates = &search_spaces[alloc.dci_index as usize][cfi.index()];
+                assert!(candidates[alloc.aggregation_level.index()].contains(&alloc.start_cce));
+                assert!(!(union & alloc.mask).any());
+                union |= alloc.mask;
+            }
+            assert_eq!(union, total_mask);
+        }
+        assert!(deep_count > shallow_count);
+    }
+
+    #[test]
+    fn failed_shuffle_keeps_allocations() {
+        let mut buffer = ShufflingAllocation::new(8, Displacement::default());
+        let mut budget = BudgetCounter::default();
+        // First blocker may move to CCE 4, the second one has no other candidate
+        buffer
+            .try_alloc(&dci(L1, 0), &search_space(L1, &[0, 4]), 0, &mut budget)
+            .unwrap();
+        buffer
+            .try_alloc(&dci(L1, 1), &search_space(L1, &[2]), 0, &mut budget)
+            .unwrap();
+        let before = buffer.get_allocs();
+
+        // CCEs 0-3 are free only if both blockers move, the first move must be undone
+        let new_dci = dci(L4, 2);
+        let new_search_space = search_space(L4, &[0]);
+        assert!(buffer
+            .shuffle(&new_dci, &new_search_space, &mut budget)
+            .is_err());
+        assert!(buffer.is_consistent());
+        assert_eq!(buffer.get_allocs(), before);
+
+        // Without the second blocker the first one moves
+        buffer.release(1, &mut budget);
+        buffer
+            .shuffle(&new_dci, &new_search_space, &mut budget)
+            .unwrap();
+        assert!(buffer.is_consistent());
+        let (allocs, _) = buffer.get_allocs();
+        let start_cces: Vec<_> = allocs
+            .iter()
+            .map(|alloc| (alloc.dci_index, alloc.start_cce))
+            .collect();
+        assert_eq!(start_cces, [(0, 4), (2, 0)]);
+    }
 }
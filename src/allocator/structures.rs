@@ -1,3 +1,18 @@
+use crate::defines;
+use crate::types::bitset::BoundedBitset;
+use crate::types::bounded_vec::BoundedVec;
+use crate::types::cfi::Cfi;
+use crate::types::rnti::Rnti;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::cmp::Reverse;
+use std::time::{Duration, Instant};
+
+use super::dci_format::DciFormat;
+use super::link_adaptation::{self, PdcchLinkModel};
+
 #[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 #[repr(u8)]
 pub enum PdcchAggregation {
@@ -52,35 +67,145 @@ impl PdcchAggregation {
 
 pub type CceCountTable = [u8; defines::NUM_OF_CFI];
 
+/// PDSCH resource elements in one PRB pair for each CFI, 0 for CFIs the cell can't use
+pub type PdschReTable = [u16; defines::NUM_OF_CFI];
+
+/// Smallest and biggest CFI with CCEs for PDCCH: 1..3 for wide cells, 2..4 for 1.4 MHz
+pub fn cfi_range(cce_count_table: &CceCountTable) -> (Cfi, Cfi) {
+    let mut usable = Cfi::list()
+        .into_iter()
+        .filter(|cfi| cce_count_table[cfi.index()] != 0);
+    let min_cfi = usable.next().unwrap_or_default();
+    let max_cfi = usable.next_back().unwrap_or(min_cfi);
+    (min_cfi, max_cfi)
+}
+
 pub type CcePositions = BoundedVec<u8, 6>;
 pub type CfiSearchSpace = [CcePositions; 4];
 pub type SfSearchSpace = [CfiSearchSpace; defines::NUM_OF_CFI];
-pub type SearchSpace = [SfSearchSpace; 10];
+pub type SearchSpace = [SfSearchSpace; defines::NUM_OF_SF];
+
+/// Copy of `SfSearchSpace` without heap memory, to keep search spaces of allocated DCIs
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CompactSearchSpace {
+    positions: [[[u8; CcePositions::capacity()]; 4]; defines::NUM_OF_CFI],
+    lens: [[u8; 4]; defines::NUM_OF_CFI],
+}
+
+impl CompactSearchSpace {
+    /// Copies candidates of the CFIs below `max_cfi`
+    pub fn below(search_space: &SfSearchSpace, max_cfi: Cfi) -> Self {
+        let mut compact = CompactSearchSpace::default();
+        for cfi in Cfi::list().into_iter().take_while(|cfi| *cfi < max_cfi) {
+            for aggregation_level in PdcchAggregation::list() {
+                let positions = &search_space[cfi.index()][aggregation_level.index()];
+                compact.positions[cfi.index()][aggregation_level.index()][..positions.len()]
+                    .copy_from_slice(positions);
+                compact.lens[cfi.index()][aggregation_level.index()] = positions.len() as u8;
+            }
+        }
+        compact
+    }
+
+    pub fn get(&self, cfi: Cfi, aggregation_level: PdcchAggregation) -> &[u8] {
+        let len = self.lens[cfi.index()][aggregation_level.index()] as usize;
+        &self.positions[cfi.index()][aggregation_level.index()][..len]
+    }
+}
 
+// Y_k hashing parameters, 36.213 9.1.1
+const Y_A: u32 = 39827;
+const Y_D: u32 = 65537;
+
+/// Y_k = (A * Y_k-1) mod D, Y_-1 = n_RNTI, k = subframe.
+/// Y_k-1 < D, so A * Y_k-1 < 39827 * 65537 < 2^32 and never overflows u32
+pub fn calculate_y_k(rnti: &Rnti, subframe: usize) -> u32 {
+    let mut y_k = rnti.0 as u32;
+    for _ in 0..=subframe {
+        y_k = (Y_A * y_k) % Y_D;
+    }
+    y_k
+}
+
+/// UE-specific search space for every subframe of the radio frame
 pub fn calculate_search_space(rnti: &Rnti, cce_count_table: &CceCountTable) -> SearchSpace {
+    calculate_carrier_search_space(rnti, None, cce_count_table)
+}
+
+/// UE-specific search space of DCIs scheduling the cell with carrier indicator `carrier_indicator`.
+/// `cce_count_table` belongs to the cell carrying PDCCH. `None` if CIF is not configured
+pub fn calculate_carrier_search_space(
+    rnti: &Rnti,
+    carrier_indicator: Option<u8>,
+    cce_count_table: &CceCountTable,
+) -> SearchSpace {
     let mut search_space = SearchSpace::default();
 
-    let mut y_k: u32 = rnti.0 as u32;
-    const A: u32 = 39827;
-    const D: u32 = 65537;
+    let mut y_k = rnti.0 as u32; // Y_-1
+    for sf_search_space in search_space.iter_mut() {
+        y_k = (Y_A * y_k) % Y_D;
+        *sf_search_space = calculate_sf_search_space(y_k, carrier_indicator, cce_count_table);
+    }
 
-    for sf in 0..10 {
-        for cfi in Cfi::list() {
-            let cce_count = cce_count_table[cfi.index()]; // N_CCE,k
+    search_space
+}
 
-            for aggregation_level in PdcchAggregation::list() {
-                let cce_mod = cce_count as u32 / aggregation_level.size() as u32; // [N_CCE,k / L]
-                if cce_mod == 0 {
-                    continue;
+/// UE-specific search space for one subframe: L * ((Y_k + m') mod [N_CCE,k / L]) (36.213 9.1.1),
+/// m' = m + M(L) * n_CI with CIF configured and m' = m otherwise.
+/// The same Y_k is used for all CFIs, aggregation levels and candidates.
+/// Candidates that coincide for small N_CCE,k are listed once
+pub fn calculate_sf_search_space(
+    y_k: u32,
+    carrier_indicator: Option<u8>,
+    cce_count_table: &CceCountTable,
+) -> SfSearchSpace {
+    debug_assert!(carrier_indicator.is_none_or(|n_ci| n_ci <= defines::MAX_CARRIER_INDICATOR));
+    let mut search_space = SfSearchSpace::default();
+
+    for cfi in Cfi::list() {
+        let cce_count = cce_count_table[cfi.index()]; // N_CCE,k
+
+        for aggregation_level in PdcchAggregation::list() {
+            let cce_mod = cce_count as u32 / aggregation_level.size() as u32; // [N_CCE,k / L]
+            if cce_mod == 0 {
+                continue;
+            }
+
+            let location_count = aggregation_level.location_count() as u32; // M(L)
+            let carrier_offset = location_count * carrier_indicator.unwrap_or(0) as u32;
+            for m in 0..location_count {
+                let start_cce =
+                    aggregation_level.size() as u32 * ((y_k + m + carrier_offset) % cce_mod);
+                let positions = &mut search_space[cfi.index()][aggregation_level.index()];
+                if !positions.contains(&(start_cce as u8)) {
+                    positions.push(start_cce as u8).unwrap();
                 }
+            }
+        }
+    }
 
-                for m in 0..aggregation_level.location_count() as u32 {
-                    // As described in 36.213 9.1.1
-                    y_k = (A * y_k) % D;
-                    let start_cce = aggregation_level.size() as u32 * ((y_k + m) % cce_mod);
-                    search_space[sf][cfi.index()][aggregation_level.index()]
-                        .push(start_cce as u8)
-                        .unwrap();
+    search_space
+}
+
+/// Common search space: Y_k = 0, L4 and L8 only, first 16 CCEs (36.213 9.1.1).
+/// It doesn't depend on RNTI and subframe
+pub fn calculate_common_search_space(cce_count_table: &CceCountTable) -> SfSearchSpace {
+    let mut search_space = SfSearchSpace::default();
+
+    for cfi in Cfi::list() {
+        let cce_count = cce_count_table[cfi.index()].min(COMMON_SEARCH_SPACE_CCE_COUNT);
+
+        for aggregation_level in PdcchAggregation::list() {
+            let cce_mod = cce_count as u32 / aggregation_level.size() as u32;
+            if cce_mod == 0 {
+                continue;
+            }
+
+            for m in 0..aggregation_level.common_location_count() as u32 {
+                let start_cce = aggregation_level.size() as u32 * (m % cce_mod);
+                let positions = &mut search_space[cfi.index()][aggregation_level.index()];
+                if !positions.contains(&(start_cce as u8)) {
+                    positions.push(start_cce as u8).unwrap();
                 }
             }
         }
@@ -89,5 +214,752 @@ pub fn calculate_search_space(rnti: &Rnti, cce_count_table: &CceCountTable) -> S
     search_space
 }
 
+/// Number of CCEs covered by common search space
+pub const COMMON_SEARCH_SPACE_CCE_COUNT: u8 = 16;
+
+/// Search space in which DCI is transmitted
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SearchSpaceType {
+    /// SI-RNTI, P-RNTI, RA-RNTI, TPC-RNTI and C-RNTI with formats 0/1A
+    Common,
+    #[default]
+    UeSpecific,
+    /// Any of both, allowed for C-RNTI
+    Any,
+}
+
+impl SearchSpaceType {
+    /// Candidate positions for this search space type
+    pub fn select<'a>(
+        &self,
+        ue_specific: &'a SfSearchSpace,
+        common: &'a SfSearchSpace,
+    ) -> Cow<'a, SfSearchSpace> {
+        match self {
+            SearchSpaceType::UeSpecific => Cow::Borrowed(ue_specific),
+            SearchSpaceType::Common => Cow::Borrowed(common),
+            SearchSpaceType::Any => {
+                let mut search_space = ue_specific.clone();
+                for cfi in Cfi::list() {
+                    for aggregation_level in PdcchAggregation::list() {
+                        let positions = &mut search_space[cfi.index()][aggregation_level.index()];
+                        for &start_cce in common[cfi.index()][aggregation_level.index()].iter() {
+                            if !positions.contains(&start_cce) {
+                                // UE-specific and common candidates together never exceed 6
+                                positions.push(start_cce).unwrap();
+                            }
+                        }
+                    }
+                }
+                Cow::Owned(search_space)
+            }
+        }
+    }
+}
+
 // TODO: proper value and placement
-pub const MAX_PDCCH: usize = 16;
\ No newline at end of file
+pub const MAX_PDCCH: usize = 16;
+
+pub type CceMask = BoundedBitset<{ defines::NUM_OF_CCES }>;
+
+/// Placed DCI, common for all allocators
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PdcchAllocation {
+    // Location data
+    pub aggregation_level: PdcchAggregation,
+    pub format: DciFormat,
+    pub start_cce: u8,
+    pub rnti: Rnti,
+    /// n_CI of the cell scheduled by this DCI, `None` if CIF is not configured
+    pub carrier_indicator: Option<u8>,
+    /// Index returned by `allocate_dci`, used to release the DCI
+    pub dci_index: u8,
+    /// DCI with higher priority may preempt DCIs with lower one, 0 is the lowest
+    pub priority: u8,
+
+    pub(crate) mask: CceMask,
+}
+
+impl PdcchAllocation {
+    /// CCEs occupied by this allocation
+    pub fn mask(&self) -> &CceMask {
+        &self.mask
+    }
+}
+
+/// Aggregation levels a DCI may use, in order of suitability for the link
+#[derive(Clone, Debug, PartialEq)]
+pub enum AggregationChoice {
+    /// `preferred` first, then `alternatives`: larger levels are more robust and go first
+    /// from the smallest one, then smaller levels from the largest one
+    Levels {
+        preferred: PdcchAggregation,
+        alternatives: BoundedVec<PdcchAggregation, 4>,
+    },
+    /// Levels chosen by `PdcchLinkModel::choose` for DCI with `payload_bits` at `sinr_db`:
+    /// the smallest level which reaches the target BLER of the model first, then larger
+    /// levels which fit into CCEs of `cfi`
+    LinkQuality {
+        model: PdcchLinkModel,
+        sinr_db: f32,
+        payload_bits: u16,
+        cfi: Cfi,
+    },
+}
+
+impl AggregationChoice {
+    /// Link quality from wideband CQI 1..15, CQI 0 is below CQI 1
+    pub fn from_cqi(model: &PdcchLinkModel, cqi: u8, payload_bits: u16, cfi: Cfi) -> Self {
+        AggregationChoice::LinkQuality {
+            model: *model,
+            sinr_db: link_adaptation::cqi_sinr_db(cqi),
+            payload_bits,
+            cfi,
+        }
+    }
+
+    /// Levels in the order they are tried
+    pub fn levels(&self) -> BoundedVec<PdcchAggregation, 4> {
+        match self {
+            AggregationChoice::Levels {
+                preferred,
+                alternatives,
+            } => {
+                let mut levels = BoundedVec::new();
+                let _ = levels.push(*preferred);
+                let larger = PdcchAggregation::list()
+                    .into_iter()
+                    .filter(|l| l > preferred);
+                let smaller = PdcchAggregation::list()
+                    .into_iter()
+                    .rev()
+                    .filter(|l| l < preferred);
+                for level in larger.chain(smaller) {
+                    if alternatives.contains(&level) {
+                        let _ = levels.push(level);
+                    }
+                }
+                levels
+            }
+            AggregationChoice::LinkQuality {
+                model,
+                sinr_db,
+                payload_bits,
+                cfi,
+            } => model.choose(*sinr_db, *payload_bits, *cfi).levels(),
+        }
+    }
+}
+
+/// DCI to place, also an entry of the TTI candidate list for batch allocation
+#[derive(Clone, Debug)]
+pub struct DciRequest<'a> {
+    pub aggregation_level: PdcchAggregation,
+    pub format: DciFormat,
+    /// UE-specific search space, it is not used for `SearchSpaceType::Common`
+    pub search_space: Cow<'a, SfSearchSpace>,
+    pub search_space_type: SearchSpaceType,
+    pub rnti: Rnti,
+    /// n_CI of the cell scheduled by the DCI, `None` if CIF is not configured
+    pub carrier_indicator: Option<u8>,
+    /// Used by `allocate_dci_preempting` of later DCIs
+    pub priority: u8,
+}
+
+impl<'a> DciRequest<'a> {
+    /// DCI of format 1A in UE-specific search space without CIF, with priority 0
+    pub fn new(
+        aggregation_level: PdcchAggregation,
+        search_space: &'a SfSearchSpace,
+        rnti: Rnti,
+    ) -> Self {
+        DciRequest {
+            aggregation_level,
+            format: DciFormat::F1A,
+            search_space: Cow::Borrowed(search_space),
+            search_space_type: SearchSpaceType::UeSpecific,
+            rnti,
+            carrier_indicator: None,
+            priority: 0,
+        }
+    }
+
+    /// The same DCI at another aggregation level, search space is borrowed
+    pub fn at_level(&self, aggregation_level: PdcchAggregation) -> DciRequest<'_> {
+        DciRequest {
+            aggregation_level,
+            search_space: Cow::Borrowed(&self.search_space),
+            ..*self
+        }
+    }
+
+    /// Weight of the DCI in the allocated subset. Every DCI counts, higher priority counts more
+    pub fn weight(&self) -> u32 {
+        self.priority as u32 + 1
+    }
+
+    /// Candidate positions of the search space type of the DCI
+    pub fn candidates<'b>(
+        &'b self,
+        common_search_space: &'b SfSearchSpace,
+    ) -> Cow<'b, SfSearchSpace> {
+        self.search_space_type
+            .select(&self.search_space, common_search_space)
+    }
+
+    /// Number of candidate positions at `cfi` not overlapping `mask`
+    pub fn free_position_count(
+        &self,
+        common_search_space: &SfSearchSpace,
+        mask: &CceMask,
+        cfi: Cfi,
+    ) -> usize {
+        let size = self.aggregation_level.size();
+        self.candidates(common_search_space)[cfi.index()][self.aggregation_level.index()]
+            .iter()
+            .filter(|&&start_cce| {
+                (start_cce as usize..start_cce as usize + size)
+                    .all(|cce| cce < mask.size() && !mask.test(cce))
+            })
+            .count()
+    }
+}
+
+/// Order in which batch allocation places DCIs. DCIs with higher priority always go first
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BatchOrder {
+    /// DCI with the fewest free candidate positions, recalculated after every allocation
+    #[default]
+    MostConstrainedFirst,
+    LargestAggregationFirst,
+    /// Places as many DCIs as possible when priorities are equal
+    SmallestAggregationFirst,
+    /// DCI with the fewest candidate positions at its aggregation level
+    SmallestSearchSpaceFirst,
+    /// Order of the list
+    AsGiven,
+}
+
+impl BatchOrder {
+    pub const fn list() -> [BatchOrder; 5] {
+        [
+            BatchOrder::MostConstrainedFirst,
+            BatchOrder::LargestAggregationFirst,
+            BatchOrder::SmallestAggregationFirst,
+            BatchOrder::SmallestSearchSpaceFirst,
+            BatchOrder::AsGiven,
+        ]
+    }
+
+    /// Indices of `dcis` in placement order. `MostConstrainedFirst` depends on occupied CCEs
+    /// and is only sorted by priority here
+    pub fn sort(
+        &self,
+        dcis: &[DciRequest],
+        common_search_space: &SfSearchSpace,
+        cfi: Cfi,
+    ) -> Vec<usize> {
+        let mut order: Vec<usize> = (0..dcis.len()).collect();
+        order.sort_by_key(|&i| {
+            let dci = &dcis[i];
+            let key = match self {
+                BatchOrder::LargestAggregationFirst => {
+                    PdcchAggregation::L8.index() - dci.aggregation_level.index()
+                }
+                BatchOrder::SmallestAggregationFirst => dci.aggregation_level.index(),
+                BatchOrder::SmallestSearchSpaceFirst => {
+                    let candidates = dci.candidates(common_search_space);
+                    candidates[cfi.index()][dci.aggregation_level.index()].len()
+                }
+                BatchOrder::MostConstrainedFirst | BatchOrder::AsGiven => 0,
+            };
+            (Reverse(dci.priority), key)
+        });
+        order
+    }
+}
+
+/// Expected PDSCH transmission of a DCI, used to choose CFI together with PDSCH
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PdschPayload {
+    /// Transport block size
+    pub bits: u32,
+    /// Spectral efficiency of the modulation and coding scheme
+    pub bits_per_re: f32,
+}
+
+impl PdschPayload {
+    /// PRBs needed for the payload with `re_per_prb` PDSCH REs in every PRB pair, `None` if
+    /// the payload can not be carried: spectral efficiency is not positive or there are no REs
+    pub fn prb_count(&self, re_per_prb: u16) -> Option<u32> {
+        (self.bits_per_re > 0.0 && re_per_prb > 0)
+            .then(|| (self.bits as f32 / (self.bits_per_re * re_per_prb as f32)).ceil() as u32)
+    }
+}
+
+/// Choice of CFI by allocators. Except `Fixed`, `get_allocs` returns the lowest allowed CFI
+/// at which all allocated DCIs fit, even if CFI grew during the TTI
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CfiPolicy {
+    /// CFI grows when a DCI does not fit
+    #[default]
+    Minimize,
+    /// Only this CFI is used
+    Fixed(Cfi),
+    /// CFI does not grow above the cap. Scheduler may set a cap per subframe before `new_tti`
+    Cap(Cfi),
+    /// TTI starts from the CFI of the previous TTI, lower CFIs are used only by `get_allocs`
+    LastTti,
+}
+
+impl CfiPolicy {
+    /// First and last CFI allowed in a TTI. `min_cfi` and `max_cfi` are CFIs of the cell
+    pub fn cfi_range(&self, min_cfi: Cfi, max_cfi: Cfi, last_cfi: Cfi) -> (Cfi, Cfi) {
+        let clamp = |cfi: Cfi| cfi.clamp(min_cfi, max_cfi);
+        match *self {
+            CfiPolicy::Minimize => (min_cfi, max_cfi),
+            CfiPolicy::Fixed(cfi) => (clamp(cfi), clamp(cfi)),
+            CfiPolicy::Cap(cfi) => (min_cfi, clamp(cfi)),
+            CfiPolicy::LastTti => (clamp(last_cfi), max_cfi),
+        }
+    }
+}
+
+/// Limit of the allocator work in one TTI, the search stops at the first limit reached
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Budget {
+    /// Search steps: expanded tree nodes, moves of the shuffling, nodes of the exact search
+    pub max_steps: Option<usize>,
+    /// Time from the start of the TTI
+    pub time: Option<Duration>,
+}
+
+/// Deadline is checked once in this number of steps, `Instant::now` is not free
+const STEPS_PER_CLOCK_CHECK: usize = 64;
+
+/// Work of an allocator in the current TTI
+#[derive(Clone, Debug, Default)]
+pub(crate) struct BudgetCounter {
+    budget: Budget,
+    steps: usize,
+    deadline: Option<Instant>,
+    exhausted: bool,
+    /// Search was cut by a size limit rather than by the budget
+    truncated: bool,
+}
+
+impl BudgetCounter {
+    /// Sets the budget and starts counting it from now
+    pub(crate) fn set_budget(&mut self, budget: Budget) {
+        self.budget = budget;
+        self.new_tti();
+    }
+
+    pub(crate) fn new_tti(&mut self) {
+        self.steps = 0;
+        self.deadline = self.budget.time.map(|time| Instant::now() + time);
+        self.exhausted = false;
+        self.truncated = false;
+    }
+
+    /// Counts one search step, false once the budget is exhausted
+    pub(crate) fn step(&mut self) -> bool {
+        if self.exhausted {
+            return false;
+        }
+        self.steps += 1;
+        let out_of_steps = self
+            .budget
+            .max_steps
+            .is_some_and(|max_steps| self.steps > max_steps);
+        let out_of_time = self.steps.is_multiple_of(STEPS_PER_CLOCK_CHECK)
+            && self
+                .deadline
+                .is_some_and(|deadline| Instant::now() >= deadline);
+        self.exhausted = out_of_steps || out_of_time;
+        !self.exhausted
+    }
+
+    pub(crate) fn is_exhausted(&self) -> bool {
+        self.exhausted
+    }
+
+    pub(crate) fn truncate(&mut self) {
+        self.truncated = true;
+    }
+
+    pub(crate) fn exhaustive(&self) -> bool {
+        !self.exhausted && !self.truncated
+    }
+}
+
+/// First candidate tried by the sequential and shuffling allocators
+#[derive(Clone, Debug, Default)]
+pub enum StartPosition {
+    /// Random candidate from an RNG seeded from entropy, allocations can not be reproduced
+    #[default]
+    Random,
+    /// Random candidate from the given RNG, the same seed gives the same allocations
+    Seeded(Box<StdRng>),
+    /// Candidate number of the DCI in the TTI plus `first`, without any randomness
+    Counter { first: u32, next: u32 },
+}
+
+impl StartPosition {
+    pub fn seeded(seed: u64) -> Self {
+        StartPosition::Seeded(Box::new(StdRng::seed_from_u64(seed)))
+    }
+
+    /// Counter starting from `first` in every TTI
+    pub fn counter(first: u32) -> Self {
+        StartPosition::Counter { first, next: first }
+    }
+
+    /// Start position whose state is fully kept by `clone`: `Random` draws from its own RNG
+    /// seeded from entropy, so that allocators can save the state in `checkpoint` and
+    /// restore it in `rollback`
+    pub fn restorable(self) -> Self {
+        match self {
+            StartPosition::Random => StartPosition::Seeded(Box::new(StdRng::from_entropy())),
+            start_position => start_position,
+        }
+    }
+
+    pub fn new_tti(&mut self) {
+        if let StartPosition::Counter { first, next } = self {
+            *next = *first;
+        }
+    }
+
+    /// Offset of the first candidate for the next DCI, taken modulo the number of candidates
+    pub fn next_offset(&mut self) -> usize {
+        match self {
+            StartPosition::Random => rand::thread_rng().gen::<u32>() as usize,
+            StartPosition::Seeded(rng) => rng.gen::<u32>() as usize,
+            StartPosition::Counter { next, .. } => {
+                let offset = *next;
+                *next = next.wrapping_add(1);
+                offset as usize
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::allocator::test_fixture::dci;
+
+    /// (RNTI, subframe, CFI, expected Y_k, expected candidates for L1, L2, L4, L8)
+    type Reference = (u16, usize, Cfi, u32, [&'static [u8]; 4]);
+
+    fn check_references(cce_count_table: &CceCountTable, references: &[Reference]) {
+        for &(rnti, sf, cfi, y_k, candidates) in references {
+            let rnti = Rnti(rnti);
+            assert_eq!(calculate_y_k(&rnti, sf), y_k, "Y_k for {} sf {}", rnti, sf);
+
+            let search_space = calculate_search_space(&rnti, cce_count_table);
+            for aggregation_level in PdcchAggregation::list() {
+                assert_eq!(
+                    &search_space[sf][cfi.index()][aggregation_level.index()][..],
+                    candidates[aggregation_level.index()],
+                    "{} sf {} {:?} {:?}",
+                    rnti,
+                    sf,
+                    cfi,
+                    aggregation_level,
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn y_k_sequence() {
+        let expected = [
+            35336, 50871, 28499, 59907, 41604, 56074, 20386, 40866, 24324, 49551,
+        ];
+        for (sf, &y_k) in expected.iter().enumerate() {
+            assert_eq!(calculate_y_k(&Rnti(70), sf), y_k);
+        }
+    }
+
+    #[test]
+    fn ue_specific_search_space_reference() {
+        check_references(
+            &[17, 50, 84, 0],
+            &[
+                (
+                    61,
+                    0,
+                    Cfi::One,
+                    4578,
+                    [
+                        &[5, 6, 7, 8, 9, 10],
+                        &[4, 6, 8, 10, 12, 14],
+                        &[8, 12],
+                        &[0, 8],
+                    ],
+                ),
+                (
+                    61,
+                    5,
+                    Cfi::Two,
+                    46992,
+                    [
+                        &[42, 43, 44, 45, 46, 47],
+                        &[34, 36, 38, 40, 42, 44],
+                        &[0, 4],
+                        &[0, 8],
+                    ],
+                ),
+                (
+                    1000,
+                    9,
+                    Cfi::Three,
+                    43139,
+                    [
+                        &[47, 48, 49, 50, 51, 52],
+                        &[10, 12, 14, 16, 18, 20],
+                        &[20, 24],
+                        &[72, 0],
+                    ],
+                ),
+                (
+                    65523,
+                    3,
+                    Cfi::Three,
+                    1126,
+                    [
+                        &[34, 35, 36, 37, 38, 39],
+                        &[68, 70, 72, 74, 76, 78],
+                        &[52, 56],
+                        &[48, 56],
+                    ],
+                ),
+            ],
+        );
+    }
+
+    #[test]
+    fn ue_specific_search_space_small_cce_count() {
+        // Candidates wrap around [N_CCE,k / L] and repeated ones are dropped
+        check_references(
+            &[2, 12, 33, 0],
+            &[
+                (100, 0, Cfi::One, 50480, [&[0, 1], &[0], &[], &[]]),
+                (
+                    1,
+                    1,
+                    Cfi::Two,
+                    63455,
+                    [&[11, 0, 1, 2, 3, 4], &[10, 0, 2, 4, 6, 8], &[8, 0], &[0]],
+                ),
+                (
+                    65535,
+                    7,
+                    Cfi::Three,
+                    51262,
+                    [
+                        &[13, 14, 15, 16, 17, 18],
+                        &[28, 30, 0, 2, 4, 6],
+                        &[24, 28],
+                        &[16, 24],
+                    ],
+                ),
+            ],
+        );
+    }
+
+    #[test]
+    fn carrier_indicator_offset() {
+        let cce_count_table = [17, 50, 84, 0];
+        let rnti = Rnti(61);
+
+        // n_CI = 0 is the scheduling cell itself and matches search space without CIF
+        assert_eq!(
+            calculate_carrier_search_space(&rnti, Some(0), &cce_count_table),
+            calculate_search_space(&rnti, &cce_count_table)
+        );
+
+        // Y_0 = 4578, m' = m + M(L) * n_CI
+        let search_space = calculate_carrier_search_space(&rnti, Some(1), &cce_count_table);
+        let cfi_search_space = &search_space[0][Cfi::Two.index()];
+        assert_eq!(
+            &cfi_search_space[PdcchAggregation::L1.index()][..],
+            &[34, 35, 36, 37, 38, 39]
+        );
+        assert_eq!(
+            &cfi_search_space[PdcchAggregation::L2.index()][..],
+            &[18, 20, 22, 24, 26, 28]
+        );
+        assert_eq!(
+            &cfi_search_space[PdcchAggregation::L4.index()][..],
+            &[32, 36]
+        );
+        assert_eq!(
+            &cfi_search_space[PdcchAggregation::L8.index()][..],
+            &[16, 24]
+        );
+    }
+
+    #[test]
+    fn candidates_fit_into_pdcch() {
+        let cce_count_table = [17, 50, 84, 0];
+        for rnti in (1..=u16::MAX).step_by(97) {
+            let search_space = calculate_search_space(&Rnti(rnti), &cce_count_table);
+            for sf_search_space in search_space.iter() {
+                for cfi in Cfi::list() {
+                    for aggregation_level in PdcchAggregation::list() {
+                        for &start_cce in
+                            sf_search_space[cfi.index()][aggregation_level.index()].iter()
+                        {
+                            assert_eq!(start_cce as usize % aggregation_level.size(), 0);
+                            assert!(
+                                start_cce as usize + aggregation_level.size()
+                                    <= cce_count_table[cfi.index()] as usize
+                            );
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn common_search_space() {
+        let search_space = calculate_common_search_space(&[17, 50, 84, 0]);
+
+        for cfi in [Cfi::One, Cfi::Two, Cfi::Three] {
+            assert!(search_space[cfi.index()][PdcchAggregation::L1.index()].is_empty());
+            assert!(search_space[cfi.index()][PdcchAggregation::L2.index()].is_empty());
+            assert_eq!(
+                &search_space[cfi.index()][PdcchAggregation::L4.index()][..],
+                &[0, 4, 8, 12]
+            );
+            assert_eq!(
+                &search_space[cfi.index()][PdcchAggregation::L8.index()][..],
+                &[0, 8]
+            );
+        }
+        assert!(search_space[Cfi::Four.index()]
+            .iter()
+            .all(|positions| positions.is_empty()));
+    }
+
+    #[test]
+    fn common_search_space_small_cell() {
+        // 6 PRB: less than 16 CCEs, candidates wrap and must not repeat
+        let search_space = calculate_common_search_space(&[0, 2, 10, 0]);
+
+        assert!(search_space[Cfi::One.index()][PdcchAggregation::L4.index()].is_empty());
+        assert!(search_space[Cfi::Two.index()][PdcchAggregation::L4.index()].is_empty());
+        assert_eq!(
+            &search_space[Cfi::Three.index()][PdcchAggregation::L4.index()][..],
+            &[0, 4]
+        );
+        assert_eq!(
+            &search_space[Cfi::Three.index()][PdcchAggregation::L8.index()][..],
+            &[0]
+        );
+    }
+
+    #[test]
+    fn any_search_space_merges_candidates() {
+        let cce_count_table = [17, 50, 84, 0];
+        let common = calculate_common_search_space(&cce_count_table);
+        let ue_specific = calculate_search_space(&Rnti(1), &cce_count_table)[0].clone();
+
+        let any = SearchSpaceType::Any.select(&ue_specific, &common);
+        for cfi in Cfi::list() {
+            for aggregation_level in PdcchAggregation::list() {
+                let positions = &any[cfi.index()][aggregation_level.index()];
+                for start_cce in ue_specific[cfi.index()][aggregation_level.index()]
+                    .iter()
+                    .chain(common[cfi.index()][aggregation_level.index()].iter())
+                {
+                    assert!(positions.contains(start_cce));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn batch_order() {
+        use BatchOrder::*;
+
+        let cce_count_table = [17, 50, 84, 0];
+        let request = |aggregation_level, rnti, priority| DciRequest {
+            priority,
+            ..dci(aggregation_level, Rnti(rnti), &cce_count_table, 0)
+        };
+        let dcis = [
+            request(PdcchAggregation::L1, 1, 0),
+            request(PdcchAggregation::L8, 2, 0),
+            request(PdcchAggregation::L2, 3, 1),
+            request(PdcchAggregation::L4, 4, 0),
+        ];
+
+        let common = calculate_common_search_space(&cce_count_table);
+        let sort = |order: BatchOrder, dcis: &[DciRequest]| order.sort(dcis, &common, Cfi::One);
+        assert_eq!(sort(AsGiven, &dcis), [2, 0, 1, 3]);
+        assert_eq!(sort(LargestAggregationFirst, &dcis), [2, 1, 3, 0]);
+        assert_eq!(sort(SmallestAggregationFirst, &dcis), [2, 0, 3, 1]);
+        // 17 CCEs: 6 candidates for L1, 2 for L4 and L8
+        assert_eq!(sort(SmallestSearchSpaceFirst, &dcis), [2, 1, 3, 0]);
+
+        let mut mask = CceMask::new(17);
+        assert_eq!(dcis[1].free_position_count(&common, &mask, Cfi::One), 2);
+        mask.set(0, true).unwrap();
+        assert_eq!(dcis[1].free_position_count(&common, &mask, Cfi::One), 1);
+        mask.set(8, true).unwrap();
+        assert_eq!(dcis[1].free_position_count(&common, &mask, Cfi::One), 0);
+
+        // Common search space has 4 candidates for L4, more than the UE-specific one
+        let common_dci = DciRequest {
+            search_space_type: SearchSpaceType::Common,
+            ..request(PdcchAggregation::L4, 5, 0)
+        };
+        let dcis = [common_dci, request(PdcchAggregation::L4, 4, 0)];
+        let mask = CceMask::new(17);
+        assert_eq!(sort(SmallestSearchSpaceFirst, &dcis), [1, 0]);
+        assert_eq!(dcis[0].free_position_count(&common, &mask, Cfi::One), 4);
+    }
+
+    #[test]
+    fn start_position_counter() {
+        let mut start_position = StartPosition::counter(u32::MAX - 1);
+        for _ in 0..2 {
+            let offsets: Vec<usize> = (0..3).map(|_| start_position.next_offset()).collect();
+            assert_eq!(offsets, [u32::MAX as usize - 1, u32::MAX as usize, 0]);
+            start_position.new_tti();
+        }
+    }
+
+    #[test]
+    fn aggregation_levels() {
+        use PdcchAggregation::*;
+
+        let choice = AggregationChoice::Levels {
+            preferred: L2,
+            alternatives: BoundedVec::try_from(vec![L8, L1, L4]).unwrap(),
+        };
+        assert_eq!(choice.levels().to_vec(), [L2, L4, L8, L1]);
+    }
+
+    #[test]
+    fn pdsch_prb_count() {
+        let payload = PdschPayload {
+            bits: 1000,
+            bits_per_re: 2.0,
+        };
+        assert_eq!(payload.prb_count(100), Some(5));
+        assert_eq!(payload.prb_count(120), Some(5));
+        assert_eq!(payload.prb_count(0), None);
+
+        let payload = PdschPayload {
+            bits: 1000,
+            bits_per_re: 0.0,
+        };
+        assert_eq!(payload.prb_count(100), None);
+    }
+}
@@ -0,0 +1,104 @@
+//! Cells, DCIs and allocators shared by the tests
+
+use crate::scheduler_config::{CellConfig, SchedulerConfig};
+use crate::types::rnti::Rnti;
+use crate::{defines, init, resource_allocation};
+use rand::rngs::StdRng;
+use rand::Rng;
+use std::borrow::Cow;
+
+use super::dci_format::DciFormat;
+use super::optimal_pdcch_allocator::PdcchSchedOptimal;
+use super::pdcch_allocator::PdcchAllocator;
+use super::sequential_pdcch_allocator::PdcchSchedSequential;
+use super::shuffling_pdcch_allocator::PdcchSchedShuffling;
+use super::structures::{
+    calculate_search_space, CceCountTable, CceMask, DciRequest, PdcchAggregation, SearchSpaceType,
+    StartPosition,
+};
+use super::tree_pdcch_allocator::PdcchSchedTree;
+
+/// Cell of the default config with `nof_prb` PRBs
+pub(crate) fn cell(nof_prb: u8) -> CellConfig {
+    let mut cell_config = init::cell_cfg(&SchedulerConfig::default());
+    cell_config.nof_prb = nof_prb;
+    cell_config
+}
+
+pub(crate) fn count_table(nof_prb: u8) -> CceCountTable {
+    resource_allocation::calculate_cce_count_table(&cell(nof_prb))
+}
+
+/// Every allocator with the default settings
+pub(crate) fn allocators(count_table: CceCountTable) -> [Box<dyn PdcchAllocator>; 4] {
+    [
+        Box::new(PdcchSchedTree::new(count_table)),
+        Box::new(PdcchSchedSequential::new(count_table)),
+        Box::new(PdcchSchedShuffling::new(count_table)),
+        Box::new(PdcchSchedOptimal::new(count_table)),
+    ]
+}
+
+/// Allocators which choose the first candidate by `start_position`
+pub(crate) fn start_position_allocators(
+    count_table: CceCountTable,
+    start_position: StartPosition,
+) -> [Box<dyn PdcchAllocator>; 2] {
+    [
+        Box::new(PdcchSchedSequential::with_start_position(
+            count_table,
+            start_position.clone(),
+        )),
+        Box::new(PdcchSchedShuffling::with_start_position(
+            count_table,
+            start_position,
+        )),
+    ]
+}
+
+/// DCI of format 1A in the UE-specific search space of `rnti` in `subframe`
+pub(crate) fn dci(
+    aggregation_level: PdcchAggregation,
+    rnti: Rnti,
+    count_table: &CceCountTable,
+    subframe: usize,
+) -> DciRequest<'static> {
+    DciRequest {
+        aggregation_level,
+        format: DciFormat::F1A,
+        search_space: Cow::Owned(calculate_search_space(&rnti, count_table)[subframe].clone()),
+        search_space_type: SearchSpaceType::UeSpecific,
+        rnti,
+        carrier_indicator: None,
+        priority: 0,
+    }
+}
+
+/// DCIs of random RNTIs with levels 1, 2 or 4 and priorities 0..3 in one random subframe
+pub(crate) fn random_dcis(
+    rng: &mut StdRng,
+    count_table: &CceCountTable,
+    dci_count: usize,
+) -> Vec<DciRequest<'static>> {
+    let subframe = rng.gen_range(0..defines::NUM_OF_SF);
+    (0..dci_count)
+        .map(|_| {
+            let aggregation_level = PdcchAggregation::list()[rng.gen_range(0..3)];
+            DciRequest {
+                priority: rng.gen_range(0..3),
+                ..dci(aggregation_level, Rnti(rng.gen()), count_table, subframe)
+            }
+        })
+        .collect()
+}
+
+/// Allocations do not overlap and the mask is their union
+pub(crate) fn check_allocs(s: &dyn PdcchAllocator) {
+    let (allocs, mask, _) = s.get_allocs();
+    let mut allocs_mask = CceMask::new(mask.size());
+    for alloc in allocs.iter() {
+        assert!((allocs_mask & alloc.mask).none());
+        allocs_mask |= alloc.mask;
+    }
+    assert_eq!(allocs_mask, mask);
+}
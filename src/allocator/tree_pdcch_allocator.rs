@@ -1,229 +1,409 @@
-use crate::defines;
 use crate::signalling::structs::{AllocationError, AllocationResult};
-use crate::types::bitset::BoundedBitset;
 use crate::types::bounded_vec::BoundedVec;
 use crate::types::cfi::Cfi;
-use crate::types::interval::Interval;
-use crate::types::rnti::Rnti;
-use serde::{Deserialize, Serialize};
-use std::array;
+use std::cmp::Reverse;
 
-use structures;
+use super::dci_format::CodingRateLimit;
+use super::pdcch_allocator::{CfiBuffer, CfiState, PdcchAllocator};
+use super::structures::{
+    Budget, BudgetCounter, CceCountTable, CceMask, CfiPolicy, CfiSearchSpace, DciRequest,
+    PdcchAggregation, PdcchAllocation, SfSearchSpace, MAX_PDCCH,
+};
 
 pub type PdcchSched = PdcchSchedTree;
 
 /// State for dci allocation
 #[derive(Clone, Debug)]
 pub struct PdcchSchedTree {
-    pub current_cfi: Cfi,
-    dci_index: u8,
-
-    candidate_trees: [AllocationTree; defines::NUM_OF_CFI],
+    state: CfiState<AllocationTree>,
 }
 
 impl PdcchSchedTree {
     pub fn new(cce_table: CceCountTable) -> Self {
+        Self::with_beam_width(cce_table, None)
+    }
+
+    /// Beam search: tree limited to `beam_width` best partial solutions per layer, nodes with
+    /// the same occupied CCEs are merged. Trades blocking probability for time, `beam_width` 1
+    /// is a greedy allocator
+    pub fn new_beam(cce_table: CceCountTable, beam_width: usize) -> Self {
+        Self::with_beam_width(cce_table, Some(beam_width.max(1)))
+    }
+
+    fn with_beam_width(cce_table: CceCountTable, beam_width: Option<usize>) -> Self {
         PdcchSchedTree {
-            current_cfi: Default::default(),
-            dci_index: 0,
-            candidate_trees: array::from_fn(|i| AllocationTree::new(cce_table[i])),
+            state: CfiState::new(cce_table, |cce_count| {
+                AllocationTree::new(cce_count, beam_width)
+            }),
         }
     }
+}
 
-    pub fn new_tti(&mut self) {
-        self.current_cfi = Cfi::One;
-        self.dci_index = 0;
-        for tree in self.candidate_trees.iter_mut() {
-            tree.reset();
-        }
+impl PdcchAllocator for PdcchSchedTree {
+    fn new_tti(&mut self) {
+        self.state.new_tti();
     }
 
-    pub fn increment_cfi(&mut self) -> AllocationResult {
-        //      println!("incrementing cfi");
-        //      println!("last mask: {:?}", self.candidate_trees[self.current_cfi.index()]);
-        match self.current_cfi {
-            Cfi::One => {
-                self.current_cfi = Cfi::Two;
-                Ok(())
-            }
-            Cfi::Two => {
-                self.current_cfi = Cfi::Three;
-                Ok(())
-            }
-            Cfi::Three => Err(AllocationError::NoCchSpace),
-        }
+    fn current_cfi(&self) -> Cfi {
+        self.state.current_cfi
     }
 
-    pub fn allocate_dci(
-        &mut self,
-        aggregation_level: PdcchAggregation,
-        search_space: &SfSearchSpace,
-        rnti: Rnti,
-    ) -> Result<u8, AllocationError> {
-        let start_cfi = self.current_cfi;
-        for cfi in start_cfi.iter() {
-            let res = self.candidate_trees[cfi.index()].try_alloc(
-                aggregation_level,
-                &search_space[cfi.index()],
-                rnti,
-            );
-            match res {
-                Ok(_) => (),
-                Err(_) => {
-                    self.increment_cfi()?;
-                    continue;
-                }
-            }
-        }
-        self.dci_index += 1;
-        Ok(self.dci_index - 1)
+    fn increment_cfi(&mut self) -> AllocationResult {
+        self.state.increment_cfi()
     }
 
-    pub fn get_allocs(&self) -> (BoundedVec<PdcchAlloc, MAX_PDCCH>, CceMask, Cfi) {
-        let (allocs, mask) = self.candidate_trees[self.current_cfi.index()].get_allocs();
-        (allocs, mask, self.current_cfi)
+    fn allocate_dci_in(&mut self, request: &DciRequest) -> Result<u8, AllocationError> {
+        self.state
+            .allocate(request, |tree, dci, search_space, budget| {
+                tree.try_alloc(dci, search_space, budget)
+            })
+    }
+
+    fn release_dci(&mut self, dci_index: u8) -> AllocationResult {
+        self.state.release_dci(dci_index)
+    }
+
+    fn checkpoint(&mut self) {
+        self.state.checkpoint();
+    }
+
+    fn commit(&mut self) -> AllocationResult {
+        self.state.commit()
+    }
+
+    fn rollback(&mut self) -> AllocationResult {
+        self.state.rollback()
+    }
+
+    fn get_allocs(&self) -> (BoundedVec<PdcchAllocation, MAX_PDCCH>, CceMask, Cfi) {
+        self.state.get_allocs()
+    }
+
+    fn common_search_space(&self) -> &SfSearchSpace {
+        &self.state.common_search_space
+    }
+
+    fn set_cfi_policy(&mut self, cfi_policy: CfiPolicy) {
+        self.state.set_cfi_policy(cfi_policy);
+    }
+
+    fn set_coding_rate_limit(&mut self, coding_rate_limit: Option<CodingRateLimit>) {
+        self.state.set_coding_rate_limit(coding_rate_limit);
+    }
+
+    fn set_budget(&mut self, budget: Budget) {
+        self.state.budget.set_budget(budget);
+    }
+
+    fn exhaustive(&self) -> bool {
+        self.state.budget.exhaustive()
     }
 }
 
-pub type CceMask = BoundedBitset<{ defines::NUM_OF_CCES }>;
+/// DCI placed into the tree with its search space
+type PlacedDci = (PdcchAllocation, CfiSearchSpace);
+
+/// Numbers of trees saved by `release`, layers and DCIs at the moment of checkpoint
+#[derive(Clone, Copy, Debug)]
+struct TreeCheckpoint {
+    released_count: usize,
+    layer_count: usize,
+    dci_count: usize,
+}
+
+/// Bound of partial solutions kept in a layer, 48 bytes each. Layer of more partial solutions
+/// is cut, then the tree is no longer an exhaustive search
+const MAX_TREE_NODES: usize = 1 << 18;
+
+type TreeLayer = BoundedVec<TreeNode, MAX_TREE_NODES>;
+
+/// Partial solution: start CCE of every DCI in the order of tree layers and CCEs they occupy
+#[derive(Clone, Copy, Debug)]
+struct TreeNode {
+    positions: [u8; MAX_PDCCH],
+    mask: CceMask,
+}
 
-/// Tree of allocation candidates. Path in this tree represents valid set of allocations
+/// Tree of allocation candidates. Only the lowest layer is needed, every node of it
+/// represents valid set of allocations for all DCIs of the tree
 #[derive(Clone, Debug)]
 struct AllocationTree {
     cce_count: u8,
-    alloc_count: usize,
 
-    /// Vector of (parent index, allocation)
-    allocation_buffer: BoundedVec<(Option<usize>, PdcchAlloc),  33_554_432>, // 2^25, works up to ~10 DCI
+    /// Tree layers, the last one is the lowest. Without checkpoints only the lowest layer is
+    /// kept, while a checkpoint is open the tree only grows and `rollback` drops new layers
+    layers: Vec<TreeLayer>,
+
+    /// DCIs in the order of tree layers
+    requests: BoundedVec<PlacedDci, MAX_PDCCH>,
 
-    /// Range of indices for lowest tree layer
-    last_layer: Interval<usize, { usize::MAX }>,
+    /// DCIs and layers of the trees rebuilt by `release` while a checkpoint is open
+    released: Vec<(BoundedVec<PlacedDci, MAX_PDCCH>, Vec<TreeLayer>)>,
+
+    /// Number of open checkpoints
+    checkpoint_count: usize,
+
+    /// Nodes kept in a layer by the beam search, `None` for the full tree
+    beam_width: Option<usize>,
 }
 
 impl AllocationTree {
-    fn new(cce_count: u8) -> AllocationTree {
-        AllocationTree {
+    fn new(cce_count: u8, beam_width: Option<usize>) -> AllocationTree {
+        let mut tree = AllocationTree {
             cce_count,
-            alloc_count: 0,
-            allocation_buffer: BoundedVec::new(),
-            last_layer: Interval::new(0, 0).unwrap(),
+            layers: Vec::new(),
+            requests: BoundedVec::new(),
+            released: Vec::new(),
+            checkpoint_count: 0,
+            beam_width,
+        };
+        tree.reset();
+        tree
+    }
+
+    /// Without open checkpoints only the lowest layer is kept
+    fn close_checkpoint(&mut self) {
+        self.checkpoint_count -= 1;
+        if self.checkpoint_count == 0 {
+            self.layers.drain(..self.layers.len() - 1);
+            self.released.clear();
         }
     }
 
-    fn reset(&mut self) {
-        self.alloc_count = 0;
-        self.allocation_buffer.clear();
-        self.last_layer = Interval::new(0, 0).unwrap();
+    fn layer(&self) -> &TreeLayer {
+        self.layers.last().unwrap()
     }
 
+    /// Tree without DCIs: a single node with no occupied CCEs
+    fn clear(&mut self) {
+        let mut root = BoundedVec::new();
+        root.push(TreeNode {
+            positions: [0; MAX_PDCCH],
+            mask: CceMask::new(self.cce_count as usize),
+        })
+        .unwrap();
+        self.layers.clear();
+        self.layers.push(root);
+        self.requests.clear();
+    }
+
+    /// Places the DCIs again. Out of budget or with a beam the replay may fail, then the tree
+    /// keeps only `node`, which is a valid placement of `requests`
+    fn rebuild(
+        &mut self,
+        requests: BoundedVec<PlacedDci, MAX_PDCCH>,
+        node: TreeNode,
+        budget: &mut BudgetCounter,
+    ) {
+        self.clear();
+        for (dci, search_space) in requests.iter() {
+            if self.try_alloc(dci, search_space, budget).is_err() {
+                debug_assert!(
+                    budget.is_exhausted() || self.beam_width.is_some(),
+                    "DCIs of a valid tree fit again"
+                );
+                let layer = self.layers.last_mut().unwrap();
+                layer.clear();
+                layer.push(node).unwrap();
+                self.requests = requests;
+                return;
+            }
+        }
+    }
+
+    /// Expands every node of the layer by one step of the budget. Out of budget the rest of
+    /// the layer is dropped, but the first node is expanded anyway, so the tree goes on as
+    /// a greedy allocator
     fn try_alloc(
         &mut self,
-        aggregation_level: PdcchAggregation,
+        dci: &PdcchAllocation,
         search_space: &CfiSearchSpace,
-        rnti: Rnti,
+        budget: &mut BudgetCounter,
     ) -> Result<(), ()> {
-        if self.alloc_count == 0 {
-            // First alloc
-            let _ = self.try_single_alloc(None, aggregation_level, search_space, rnti);
-        } else {
-            for i in self.last_layer.range() {
-                let _ = self.try_single_alloc(Some(i), aggregation_level, search_space, rnti);
+        if self.requests.is_full() {
+            return Err(());
+        }
+        let aggregation_level = dci.aggregation_level;
+        let layer_index = self.requests.len();
+
+        let mut next_layer: TreeLayer = BoundedVec::new();
+        'layer: for (k, node) in self.layer().iter().enumerate() {
+            if !budget.step() && k > 0 {
+                break;
+            }
+            for &start_cce in search_space[aggregation_level as usize].iter() {
+                let mut alloc_mask = CceMask::new(self.cce_count as usize);
+                alloc_mask.fill(start_cce as usize, aggregation_level.size(), true)?;
+                if (alloc_mask & node.mask).any() {
+                    continue;
+                }
+
+                let mask = alloc_mask | node.mask;
+                let mut positions = node.positions;
+                positions[layer_index] = start_cce;
+                let next_node = TreeNode { positions, mask };
+                if next_layer.push(next_node).is_err() {
+                    budget.truncate();
+                    break 'layer;
+                }
             }
         }
 
-        if self.allocation_buffer.len() == self.last_layer.len {
+        if next_layer.is_empty() {
             return Err(());
         }
+        if let Some(beam_width) = self.beam_width.filter(|&width| next_layer.len() > width) {
+            // Stable sort keeps the tree order among equal nodes
+            next_layer.sort_by_cached_key(|node| Reverse(free_block_count(&node.mask)));
+            // Beam keeps one node per set of occupied CCEs, the first of them
+            let mut kept = 0;
+            for k in 0..next_layer.len() {
+                let mask = next_layer[k].mask;
+                if next_layer[..kept].iter().any(|node| node.mask == mask) {
+                    continue;
+                }
+                if kept == beam_width {
+                    budget.truncate();
+                    break;
+                }
+                next_layer.swap(kept, k);
+                kept += 1;
+            }
+            next_layer.truncate(kept);
+        }
 
-        self.last_layer = Interval::new(self.last_layer.len, self.allocation_buffer.len()).unwrap();
-        self.alloc_count += 1;
+        self.requests
+            .push((dci.clone(), search_space.clone()))
+            .map_err(|_| ())?;
+        if self.checkpoint_count == 0 {
+            self.layers.clear();
+        }
+        self.layers.push(next_layer);
         Ok(())
     }
+}
 
-    fn try_single_alloc(
-        &mut self,
-        parent_idx: Option<usize>,
-        aggregation_level: PdcchAggregation,
-        search_space: &CfiSearchSpace,
-        rnti: Rnti,
-    ) -> Result<(), ()> {
-        let cum_mask = match parent_idx {
-            Some(index) => self
-                .allocation_buffer
-                .get(index)
-                .unwrap()
-                .1
-                .total_mask
-                .clone(),
-            None => CceMask::new(self.cce_count as usize),
-        };
-        for &start_cce in search_space[aggregation_level as usize].iter() {
+impl CfiBuffer for AllocationTree {
+    type Checkpoint = TreeCheckpoint;
+
+    fn reset(&mut self) {
+        self.clear();
+        self.released.clear();
+        self.checkpoint_count = 0;
+    }
+
+    fn dci_count(&self) -> usize {
+        self.requests.len()
+    }
 
-            let mut alloc_mask = CceMask::new(self.cce_count as usize);
-            alloc_mask.fill(start_cce as usize, aggregation_level.size(), true)?;
-            if (alloc_mask & cum_mask).any() {
+    fn contains(&self, dci_index: u8) -> bool {
+        self.requests
+            .iter()
+            .any(|request| request.0.dci_index == dci_index)
+    }
+
+    /// Rebuilds the tree without the DCI. Subset of a valid set of allocations is valid,
+    /// so the remaining DCIs always fit. While a checkpoint is open the old tree is saved
+    fn release(&mut self, dci_index: u8, budget: &mut BudgetCounter) {
+        if !self.contains(dci_index) {
+            return;
+        }
+        let node = &self.layer()[0];
+        let mut requests = BoundedVec::new();
+        let mut remaining = TreeNode {
+            positions: [0; MAX_PDCCH],
+            mask: CceMask::new(self.cce_count as usize),
+        };
+        for (request, &start_cce) in self.requests.iter().zip(node.positions.iter()) {
+            let (dci, _) = request;
+            if dci.dci_index == dci_index {
                 continue;
             }
+            remaining.positions[requests.len()] = start_cce;
+            remaining
+                .mask
+                .fill(start_cce as usize, dci.aggregation_level.size(), true)
+                .unwrap();
+            requests.push(request.clone()).unwrap();
+        }
+        if self.checkpoint_count > 0 {
+            let released = (self.requests.clone(), std::mem::take(&mut self.layers));
+            self.released.push(released);
+        }
+        self.rebuild(requests, remaining, budget);
+    }
 
-            let alloc = PdcchAlloc {
-                aggregation_level,
-                start_cce,
-                rnti,
-                mask: alloc_mask,
-                total_mask: alloc_mask | cum_mask,
-            };
-
-            self.allocation_buffer
-                .push((parent_idx, alloc))
-                .map_err(|_| ())?;
+    fn checkpoint(&mut self) -> TreeCheckpoint {
+        self.checkpoint_count += 1;
+        TreeCheckpoint {
+            released_count: self.released.len(),
+            layer_count: self.layers.len(),
+            dci_count: self.requests.len(),
         }
-        Ok(())
     }
 
-    fn get_allocs(&self) -> (BoundedVec<PdcchAlloc, MAX_PDCCH>, CceMask) {
-        if self.alloc_count == 0 {
-            return (BoundedVec::default(), CceMask::new(self.cce_count as usize));
+    fn commit(&mut self, _checkpoint: TreeCheckpoint) {
+        self.close_checkpoint();
+    }
+
+    /// Drops the layers and the trees rebuilt after the checkpoint
+    fn restore(&mut self, checkpoint: TreeCheckpoint) {
+        if self.released.len() > checkpoint.released_count {
+            let mut released = self.released.split_off(checkpoint.released_count);
+            (self.requests, self.layers) = released.swap_remove(0);
         }
-        //    println!("internal alloc count {}", self.alloc_count);
+        self.layers.truncate(checkpoint.layer_count);
+        self.requests.truncate(checkpoint.dci_count);
+        self.close_checkpoint();
+    }
 
-        let mask = self.allocation_buffer[self.last_layer.start]
-            .1
-            .clone()
-            .total_mask
-            .clone();
+    fn get_allocs(&self) -> (BoundedVec<PdcchAllocation, MAX_PDCCH>, CceMask) {
+        let node = &self.layer()[0];
         let mut allocs = BoundedVec::new();
-        let mut index = Some(self.last_layer.start);
-        while index.is_some() {
-            let (nindex, alloc) = self.allocation_buffer[index.unwrap()].clone();
+        for ((dci, _), &start_cce) in self.requests.iter().zip(node.positions.iter()) {
+            let mut mask = CceMask::new(self.cce_count as usize);
+            mask.fill(start_cce as usize, dci.aggregation_level.size(), true)
+                .unwrap();
+            let alloc = PdcchAllocation {
+                start_cce,
+                mask,
+                ..dci.clone()
+            };
             allocs.push(alloc).unwrap();
-            index = nindex;
         }
 
-        allocs.reverse();
-
-        (allocs, mask)
+        (allocs, node.mask)
     }
 }
 
-#[derive(Clone, Debug, Default)]
-pub struct PdcchAlloc {
-    // Location data
-    pub aggregation_level: PdcchAggregation,
-    pub start_cce: u8,
-
-    pub rnti: Rnti,
-
-    mask: CceMask,
-    total_mask: CceMask,
+/// Free aligned blocks of 2, 4 and 8 CCEs. Candidates of level L start at multiples of L,
+/// so the node with more free blocks blocks fewer of the next DCIs
+fn free_block_count(mask: &CceMask) -> usize {
+    [
+        PdcchAggregation::L2,
+        PdcchAggregation::L4,
+        PdcchAggregation::L8,
+    ]
+    .into_iter()
+    .map(|aggregation_level| mask.count_clear_blocks(aggregation_level.size()))
+    .sum()
 }
 
 #[cfg(test)]
 mod test {
 
     use super::*;
+    use crate::allocator::structures::{
+        calculate_carrier_search_space, calculate_search_space, SearchSpaceType,
+    };
+    use crate::allocator::test_fixture::{check_allocs, count_table, random_dcis};
     use crate::scheduler_config::SchedulerConfig;
+    use crate::types::cfi::Cfi;
+    use crate::types::rnti::Rnti;
     use crate::types::tti::TtiPoint;
     use crate::{init, resource_allocation};
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
 
     #[test]
     fn a() {
@@ -289,4 +469,204 @@ mod test {
         println!("{:?}", cfi);
         println!("{:?}", search_space[tti.to_usize() % 10]);
     }
+
+    #[test]
+    fn common_search_space() {
+        let count_table = [17, 50, 84, 0];
+
+        let mut s = PdcchSched::new(count_table);
+        s.new_tti();
+
+        let rnti = Rnti(1);
+        let search_space = calculate_search_space(&rnti, &count_table);
+        let sf_search_space = &search_space[1];
+
+        s.allocate_dci_in(&DciRequest {
+            search_space_type: SearchSpaceType::Common,
+            ..DciRequest::new(PdcchAggregation::L8, sf_search_space, Rnti::SI_RNTI)
+        })
+        .unwrap();
+        s.allocate_dci_in(&DciRequest {
+            search_space_type: SearchSpaceType::Common,
+            ..DciRequest::new(PdcchAggregation::L4, sf_search_space, Rnti::P_RNTI)
+        })
+        .unwrap();
+        s.allocate_dci_in(&DciRequest {
+            search_space_type: SearchSpaceType::Any,
+            ..DciRequest::new(PdcchAggregation::L4, sf_search_space, rnti)
+        })
+        .unwrap();
+
+        let (allocs, _, cfi) = s.get_allocs();
+        assert_eq!(cfi, Cfi::One);
+        assert_eq!(allocs.len(), 3);
+        assert!(allocs[0].start_cce < 16 && allocs[1].start_cce < 16);
+
+        // Common search space holds only L4 and L8
+        assert!(s
+            .allocate_dci_in(&DciRequest {
+                search_space_type: SearchSpaceType::Common,
+                ..DciRequest::new(PdcchAggregation::L1, sf_search_space, Rnti::SI_RNTI)
+            })
+            .is_err());
+    }
+
+    #[test]
+    fn cross_carrier_scheduling() {
+        let count_table = [17, 50, 84, 0];
+
+        let mut s = PdcchSched::new(count_table);
+        s.new_tti();
+
+        let rnti = Rnti(61);
+        let tti = TtiPoint::from(1);
+        for carrier_indicator in 0..3 {
+            let search_space =
+                calculate_carrier_search_space(&rnti, Some(carrier_indicator), &count_table);
+            s.allocate_dci_in(&DciRequest {
+                carrier_indicator: Some(carrier_indicator),
+                ..DciRequest::new(
+                    PdcchAggregation::L2,
+                    &search_space[tti.to_usize() % 10],
+                    rnti,
+                )
+            })
+            .unwrap();
+        }
+
+        let (allocs, _, _) = s.get_allocs();
+        for (carrier_indicator, alloc) in allocs.iter().enumerate() {
+            assert_eq!(alloc.rnti, rnti);
+            assert_eq!(alloc.carrier_indicator, Some(carrier_indicator as u8));
+        }
+    }
+
+    #[test]
+    fn fourth_control_symbol() {
+        let count_table = count_table(6);
+        assert_eq!(count_table, [0, 2, 4, 6]);
+
+        let mut s = PdcchSched::new(count_table);
+        s.new_tti();
+        assert_eq!(s.current_cfi(), Cfi::Two);
+
+        let tti = TtiPoint::from(1);
+        for rnti in [Rnti(10249), Rnti(7386), Rnti(45236)] {
+            let search_space = calculate_search_space(&rnti, &count_table);
+            s.allocate_dci(
+                PdcchAggregation::L2,
+                &search_space[tti.to_usize() % 10],
+                rnti,
+            )
+            .unwrap();
+        }
+
+        let (allocs, mask, cfi) = s.get_allocs();
+        assert_eq!(cfi, Cfi::Four);
+        assert_eq!(cfi.pcfich_value(6), Some(3));
+        assert_eq!(allocs.len(), 3);
+        assert!(mask.all());
+
+        let rnti = Rnti(18567);
+        let search_space = calculate_search_space(&rnti, &count_table);
+        assert_eq!(
+            s.allocate_dci(
+                PdcchAggregation::L2,
+                &search_space[tti.to_usize() % 10],
+                rnti,
+            ),
+            Err(AllocationError::NoCchSpace)
+        );
+    }
+
+    #[test]
+    fn layer_bound() {
+        // Search spaces of L1 DCIs at 100 PRB hardly overlap, 8 of them give about 6^8
+        // partial solutions
+        let count_table = count_table(100);
+        let mut rng = StdRng::seed_from_u64(6);
+        let mut s = PdcchSchedTree::new(count_table);
+        s.new_tti();
+        for dci in random_dcis(&mut rng, &count_table, 8) {
+            s.allocate_dci(PdcchAggregation::L1, &dci.search_space, dci.rnti)
+                .unwrap();
+            let cfi = s.current_cfi().index();
+            assert!(s.state.buffers[cfi].layer().len() <= MAX_TREE_NODES);
+        }
+        assert!(!s.exhaustive());
+        check_allocs(&s);
+
+        s.new_tti();
+        assert!(s.exhaustive());
+    }
+
+    #[test]
+    fn rollback_keeps_layers() {
+        let count_table = count_table(25);
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut s = PdcchSchedTree::new(count_table);
+        let dcis = random_dcis(&mut rng, &count_table, 6);
+        s.new_tti();
+        for dci in dcis[..3].iter() {
+            s.allocate_dci_in(dci).unwrap();
+        }
+        let layer_len = |s: &PdcchSchedTree| s.state.buffers[Cfi::Three.index()].layer().len();
+        let tree_len = layer_len(&s);
+        let allocs = s.get_allocs();
+
+        s.checkpoint();
+        for dci in dcis[3..].iter() {
+            let _ = s.allocate_dci_in(dci);
+        }
+        s.checkpoint();
+        s.release_dci(0).unwrap();
+        s.rollback().unwrap();
+        s.release_dci(4).unwrap();
+        s.rollback().unwrap();
+
+        // Saved layers are restored, not built again
+        assert_eq!(layer_len(&s), tree_len);
+        assert_eq!(s.get_allocs(), allocs);
+        for tree in s.state.buffers.iter() {
+            assert_eq!(tree.layers.len(), 1);
+            assert!(tree.released.is_empty());
+        }
+    }
+
+    #[test]
+    fn beam_search() {
+        let count_table = count_table(25);
+
+        let mut rng = StdRng::seed_from_u64(5);
+        let mut tree = PdcchSchedTree::new(count_table);
+        let mut wide = PdcchSchedTree::new_beam(count_table, MAX_TREE_NODES);
+        let mut greedy = PdcchSchedTree::new_beam(count_table, 1);
+        for _ in 0..100 {
+            let subframe = rng.gen_range(0..10);
+            tree.new_tti();
+            wide.new_tti();
+            greedy.new_tti();
+            for _ in 0..8 {
+                let rnti = Rnti(rng.gen());
+                let aggregation_level = PdcchAggregation::list()[rng.gen_range(0..3)];
+                let search_space = &calculate_search_space(&rnti, &count_table)[subframe];
+                assert_eq!(
+                    tree.allocate_dci(aggregation_level, search_space, rnti),
+                    wide.allocate_dci(aggregation_level, search_space, rnti)
+                );
+                let _ = greedy.allocate_dci(aggregation_level, search_space, rnti);
+            }
+            assert_eq!(tree.current_cfi(), wide.current_cfi());
+
+            let cfi = greedy.current_cfi().index();
+            assert!(greedy.state.buffers[cfi].layer().len() <= 1);
+            let (allocs, total_mask, _) = greedy.get_allocs();
+            let mut occupied = 0;
+            for alloc in allocs.iter() {
+                assert_eq!(alloc.mask.count(), alloc.aggregation_level.size());
+                occupied += alloc.aggregation_level.size();
+            }
+            assert_eq!(total_mask.count(), occupied);
+        }
+    }
 }
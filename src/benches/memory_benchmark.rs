@@ -0,0 +1,124 @@
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use schedrs::allocator::optimal_pdcch_allocator::PdcchSchedOptimal;
+use schedrs::allocator::sequential_pdcch_allocator::PdcchSchedSequential;
+use schedrs::allocator::shuffling_pdcch_allocator::PdcchSchedShuffling;
+use schedrs::scheduler_config::SchedulerConfig;
+use schedrs::signalling::sched_pdcch::{
+    calculate_search_space, PdcchAggregation, PdcchAllocator, PdcchSched, SfSearchSpace, MAX_PDCCH,
+};
+use schedrs::types::rnti::Rnti;
+use schedrs::{init, resource_allocation};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Counts heap memory of the process
+struct CountingAllocator;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+fn add_allocated(size: usize) {
+    let allocated = ALLOCATED.fetch_add(size, Ordering::Relaxed) + size;
+    PEAK.fetch_max(allocated, Ordering::Relaxed);
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            add_allocated(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = System.realloc(ptr, layout, new_size);
+        if !new_ptr.is_null() {
+            ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+            add_allocated(new_size);
+        }
+        new_ptr
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+/// Peak heap memory of one TTI in bytes over and above the memory before it
+fn tti_peak_memory(
+    s: &mut dyn PdcchAllocator,
+    dcis: &[(PdcchAggregation, SfSearchSpace, Rnti)],
+) -> usize {
+    s.new_tti();
+    let baseline = ALLOCATED.load(Ordering::Relaxed);
+    PEAK.store(baseline, Ordering::Relaxed);
+    for (aggregation_level, search_space, rnti) in dcis {
+        let _ = s.allocate_dci(*aggregation_level, search_space, *rnti);
+    }
+    PEAK.load(Ordering::Relaxed) - baseline
+}
+
+/// Prints the largest peak memory per TTI in KiB for every number of requested DCIs
+fn main() {
+    let mut cell_config = init::cell_cfg(&SchedulerConfig::default());
+    cell_config.nof_prb = 100;
+
+    let count_table = resource_allocation::calculate_cce_count_table(&cell_config);
+
+    let mut allocators: [(&str, Box<dyn PdcchAllocator>); 5] = [
+        ("pdcch", Box::new(PdcchSched::new(count_table))),
+        ("pdcch_beam", Box::new(PdcchSched::new_beam(count_table, 8))),
+        (
+            "pdcch_sequential",
+            Box::new(PdcchSchedSequential::new(count_table)),
+        ),
+        (
+            "pdcch_shuffling",
+            Box::new(PdcchSchedShuffling::new(count_table)),
+        ),
+        (
+            "pdcch_optimal",
+            Box::new(PdcchSchedOptimal::new(count_table)),
+        ),
+    ];
+
+    let mut rng = StdRng::seed_from_u64(0);
+    let ttis: Vec<Vec<_>> = (0..20)
+        .map(|_| {
+            let subframe = rng.gen_range(0..10);
+            (0..MAX_PDCCH)
+                .map(|_| {
+                    let rnti = Rnti(rng.gen());
+                    let search_space =
+                        calculate_search_space(&rnti, &count_table)[subframe].clone();
+                    (PdcchAggregation::L1, search_space, rnti)
+                })
+                .collect()
+        })
+        .collect();
+
+    let mut header = String::from("dci");
+    for (name, _) in allocators.iter() {
+        header += &format!(";{} KiB", name);
+    }
+    println!("{}", header);
+
+    for dci_count in 1..=MAX_PDCCH {
+        let mut f = format!("{}", dci_count);
+        for (_, s) in allocators.iter_mut() {
+            let peak = ttis
+                .iter()
+                .map(|dcis| tti_peak_memory(s.as_mut(), &dcis[..dci_count]))
+                .max()
+                .unwrap();
+            f += &format!(";{:.1}", peak as f64 / 1024.0);
+        }
+        println!("{}", f.replace(".", ","));
+    }
+}
@@ -1,7 +1,10 @@
-use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use criterion::{criterion_group, criterion_main, Criterion};
+use schedrs::allocator::optimal_pdcch_allocator::PdcchSchedOptimal;
+use schedrs::allocator::sequential_pdcch_allocator::PdcchSchedSequential;
+use schedrs::allocator::shuffling_pdcch_allocator::PdcchSchedShuffling;
 use schedrs::scheduler_config::SchedulerConfig;
 use schedrs::signalling::sched_pdcch::{
-    calculate_search_space, CceCountTable, PdcchAggregation, PdcchSched, SearchSpace,
+    calculate_search_space, PdcchAggregation, PdcchAllocator, PdcchSched, SearchSpace,
 };
 use schedrs::types::rnti::Rnti;
 use schedrs::types::tti::TtiPoint;
@@ -9,32 +12,43 @@ use schedrs::{init, resource_allocation};
 
 fn pdcch(
     tti: TtiPoint,
-    precalculated_values: &Vec<(
-        PdcchAggregation,
-        [[[schedrs::types::bounded_vec::BoundedVec<u8, 6>; 4]; 3]; 10],
-        Rnti,
-    )>,
-    s: &mut PdcchSched,
+    precalculated_values: &[(PdcchAggregation, SearchSpace, Rnti)],
+    s: &mut dyn PdcchAllocator,
 ) {
-    for idx in 0..precalculated_values.len() {
-        let (aggregation_level, search_space, rnti) = &precalculated_values[idx];
-
-        let _ = s.allocate_dci(*aggregation_level, &search_space[tti.to_usize() % 10], *rnti);
+    for (aggregation_level, search_space, rnti) in precalculated_values {
+        let _ = s.allocate_dci(
+            *aggregation_level,
+            &search_space[tti.to_usize() % 10],
+            *rnti,
+        );
     }
 }
 
 fn criterion_benchmark(c: &mut Criterion) {
-
     let mut cell_config = init::cell_cfg(&SchedulerConfig::default());
     cell_config.nof_prb = 100;
 
     let count_table = resource_allocation::calculate_cce_count_table(&cell_config);
 
-    let mut s = PdcchSched::new(count_table);
-    s.new_tti();
+    let allocators: [(&str, Box<dyn PdcchAllocator>); 5] = [
+        ("pdcch", Box::new(PdcchSched::new(count_table))),
+        ("pdcch_beam", Box::new(PdcchSched::new_beam(count_table, 8))),
+        (
+            "pdcch_sequential",
+            Box::new(PdcchSchedSequential::new(count_table)),
+        ),
+        (
+            "pdcch_shuffling",
+            Box::new(PdcchSchedShuffling::new(count_table)),
+        ),
+        (
+            "pdcch_optimal",
+            Box::new(PdcchSchedOptimal::new(count_table)),
+        ),
+    ];
 
     let mut tti = TtiPoint::from(1);
-    /// randomly generated DCI parameters
+    // randomly generated DCI parameters
     let data = [
         (PdcchAggregation::L1, Rnti(63107)),
         (PdcchAggregation::L1, Rnti(23953)),
@@ -55,21 +69,21 @@ fn criterion_benchmark(c: &mut Criterion) {
     ];
 
     let mut precalculated_values = Vec::new();
-    for idx in 0..data.len() {
-        let (aggregation_level, rnti) = data[idx];
-
+    for &(aggregation_level, rnti) in data.iter() {
         let search_space = calculate_search_space(&rnti, &count_table);
 
         precalculated_values.push((aggregation_level, search_space, rnti));
     }
 
-    c.bench_function("pdcch", |b| {
-        b.iter(|| {
-            tti += 1;
-            s.new_tti();
-            pdcch(tti, &precalculated_values, &mut s);
-        })
-    });
+    for (name, mut s) in allocators {
+        c.bench_function(name, |b| {
+            b.iter(|| {
+                tti += 1;
+                s.new_tti();
+                pdcch(tti, &precalculated_values, s.as_mut());
+            })
+        });
+    }
 }
 
 criterion_group!(benches, criterion_benchmark);
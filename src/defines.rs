@@ -0,0 +1,20 @@
+/// Number of control region sizes (1..4 OFDM symbols, 4 only for 1.4 MHz)
+pub const NUM_OF_CFI: usize = 4;
+
+/// Max number of CCEs in one subframe (20 MHz, CFI 3, PHICH Ng = 1/6)
+pub const NUM_OF_CCES: usize = 88;
+
+/// Number of subframes in a radio frame
+pub const NUM_OF_SF: usize = 10;
+
+/// Max number of PRB in one cell (20 MHz)
+pub const MAX_NOF_PRB: u8 = 100;
+
+/// Number of REGs in one CCE
+pub const REG_PER_CCE: usize = 9;
+
+/// Number of REGs occupied by PCFICH
+pub const PCFICH_REG_COUNT: usize = 4;
+
+/// Max value of carrier indicator field n_CI (3 bits)
+pub const MAX_CARRIER_INDICATOR: u8 = 7;
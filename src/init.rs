@@ -0,0 +1,12 @@
+use crate::scheduler_config::{CellConfig, SchedulerConfig};
+
+/// Builds cell configuration from scheduler configuration
+pub fn cell_cfg(sched_cfg: &SchedulerConfig) -> CellConfig {
+    CellConfig {
+        nof_prb: sched_cfg.nof_prb,
+        phich_resource: sched_cfg.phich_resource,
+        nof_ports: sched_cfg.nof_ports,
+        cyclic_prefix: sched_cfg.cyclic_prefix,
+        duplex: sched_cfg.duplex,
+    }
+}
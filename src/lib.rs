@@ -0,0 +1,7 @@
+pub mod allocator;
+pub mod defines;
+pub mod init;
+pub mod resource_allocation;
+pub mod scheduler_config;
+pub mod signalling;
+pub mod types;
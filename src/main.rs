@@ -1,11 +1,28 @@
-mod allocator;
-
-use rand::rngs::ThreadRng;
-use rand::Rng;
-use std::time::Instant;
-use allocator::tree_pdcch_allocator::PdcchSchedTree;
-use clap::{arg, Parser};
-
+use clap::{Parser, ValueEnum};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use schedrs::allocator::dci_format::{DciFormat, DciSizes};
+use schedrs::allocator::link_adaptation::{self, HarqFeedback, OuterLoop, PdcchLinkModel};
+use schedrs::allocator::optimal_pdcch_allocator::PdcchSchedOptimal;
+use schedrs::allocator::pdcch_allocator::PdcchAllocator;
+use schedrs::allocator::sequential_pdcch_allocator::PdcchSchedSequential;
+use schedrs::allocator::shuffling_pdcch_allocator::{Displacement, PdcchSchedShuffling};
+use schedrs::allocator::structures::{
+    calculate_search_space, cfi_range, Budget, CceCountTable, CceMask, CfiPolicy, PdcchAggregation,
+    SearchSpace, StartPosition,
+};
+use schedrs::allocator::tree_pdcch_allocator::PdcchSchedTree;
+use schedrs::scheduler_config::SchedulerConfig;
+use schedrs::types::cfi::Cfi;
+use schedrs::types::rnti::Rnti;
+use schedrs::types::tti::TtiPoint;
+use schedrs::{init, resource_allocation};
+use std::time::{Duration, Instant};
+
+/// PDCCH BLER kept by link adaptation with `--cqi`
+const TARGET_BLER: f32 = 0.01;
+/// Largest error of SINR reported by CQI with `--cqi`
+const CQI_ERROR_DB: f32 = 2.0;
 
 #[derive(Parser, Debug)]
 struct Args {
@@ -16,18 +33,213 @@ struct Args {
     // PRB count
     #[arg(short, long)]
     prb: u8,
+
+    // Allocator implementation
+    #[arg(short, long, value_enum, default_value_t = AllocatorType::Tree)]
+    allocator: AllocatorType,
+
+    // Number of partial solutions kept per layer by the beam allocator
+    #[arg(short, long, default_value_t = 8)]
+    beam_width: usize,
+
+    // Length of chains of moved DCIs in the shuffling allocator
+    #[arg(long, default_value_t = 1)]
+    shuffle_depth: u8,
+
+    // Time limit of one TTI in microseconds
+    #[arg(long)]
+    budget_us: Option<u64>,
+
+    // Limit of search steps in one TTI
+    #[arg(long)]
+    budget_steps: Option<usize>,
+
+    // Choice of CFI, `--cfi` sets the CFI of fixed and cap policies
+    #[arg(long, value_enum, default_value_t = CfiPolicyType::Minimize)]
+    cfi_policy: CfiPolicyType,
+
+    #[arg(long, default_value_t = 3, value_parser = clap::value_parser!(u8).range(1..=4))]
+    cfi: u8,
+
+    // Aggregation levels from uniformly distributed wideband CQI instead of fixed shares
+    #[arg(long)]
+    cqi: bool,
+
+    // Seed of the DCI load and of the sequential and shuffling allocators, random if not set
+    #[arg(short, long)]
+    seed: Option<u64>,
 }
-/// to run: cargo run -r -- --test N --prb M (6, 100 and several others)
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum AllocatorType {
+    Tree,
+    Beam,
+    Sequential,
+    Shuffling,
+    Optimal,
+}
+
+/// Aggregation levels of generated DCIs
+enum LevelSource {
+    /// 60/20/15/5% for levels 1/2/4/8
+    Fixed,
+    /// Level preferred by the link model for random CQI corrected by the outer loop
+    Cqi(CqiLevels),
+}
+
+/// Link adaptation of DCIs with random CQI. The true SINR differs from the one of the CQI by up
+/// to `CQI_ERROR_DB`, every generated DCI is assumed to be sent: UE misses it with BLER of the
+/// chosen level at the true SINR and the DTX or ACK goes to the outer loop shared by all UEs
+struct CqiLevels {
+    link_model: PdcchLinkModel,
+    /// Payload of format 1A in the cell
+    payload_bits: u16,
+    /// Only levels which fit into CCEs of this CFI are chosen
+    cfi: Cfi,
+    outer_loop: OuterLoop,
+    sent: u64,
+    missed: u64,
+}
+
+impl LevelSource {
+    fn gen(&mut self, rng: &mut StdRng) -> PdcchAggregation {
+        match self {
+            LevelSource::Fixed => gen_aggregation_level(rng),
+            LevelSource::Cqi(cqi) => {
+                let reported_sinr_db = link_adaptation::cqi_sinr_db(rng.gen_range(1..=15));
+                let true_sinr_db = reported_sinr_db + rng.gen_range(-CQI_ERROR_DB..=CQI_ERROR_DB);
+                let sinr_db = cqi.outer_loop.sinr_db(reported_sinr_db);
+                let aggregation_level = cqi
+                    .link_model
+                    .choose(sinr_db, cqi.payload_bits, cqi.cfi)
+                    .levels()[0];
+
+                let bler = cqi
+                    .link_model
+                    .bler(aggregation_level, cqi.payload_bits, true_sinr_db);
+                let feedback = if rng.gen::<f32>() < bler {
+                    cqi.missed += 1;
+                    HarqFeedback::Dtx
+                } else {
+                    HarqFeedback::Ack
+                };
+                cqi.outer_loop.update(feedback);
+                cqi.sent += 1;
+                aggregation_level
+            }
+        }
+    }
+
+    fn print_summary(&self) {
+        if let LevelSource::Cqi(cqi) = self {
+            println!(
+                "PDCCH BLER {:.4}, outer loop offset {:.2} dB",
+                cqi.missed as f64 / cqi.sent.max(1) as f64,
+                cqi.outer_loop.offset_db()
+            );
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum CfiPolicyType {
+    Minimize,
+    Fixed,
+    Cap,
+    LastTti,
+}
+
+impl CfiPolicyType {
+    fn policy(&self, cfi: u8) -> CfiPolicy {
+        let cfi = Cfi::from_index(cfi as usize - 1).expect("clap keeps --cfi in 1..=4");
+        match self {
+            CfiPolicyType::Minimize => CfiPolicy::Minimize,
+            CfiPolicyType::Fixed => CfiPolicy::Fixed(cfi),
+            CfiPolicyType::Cap => CfiPolicy::Cap(cfi),
+            CfiPolicyType::LastTti => CfiPolicy::LastTti,
+        }
+    }
+}
+
+impl AllocatorType {
+    fn name(&self) -> &'static str {
+        match self {
+            AllocatorType::Tree => "tree",
+            AllocatorType::Beam => "beam",
+            AllocatorType::Sequential => "sequential",
+            AllocatorType::Shuffling => "shuffling",
+            AllocatorType::Optimal => "optimal",
+        }
+    }
+
+    fn create(&self, count_table: CceCountTable, args: &Args) -> Box<dyn PdcchAllocator> {
+        let start_position = match args.seed {
+            Some(seed) => StartPosition::seeded(seed),
+            None => StartPosition::Random,
+        };
+        let mut allocator: Box<dyn PdcchAllocator> = match self {
+            AllocatorType::Tree => Box::new(PdcchSchedTree::new(count_table)),
+            AllocatorType::Beam => Box::new(PdcchSchedTree::new_beam(count_table, args.beam_width)),
+            AllocatorType::Sequential => Box::new(PdcchSchedSequential::with_start_position(
+                count_table,
+                start_position,
+            )),
+            AllocatorType::Shuffling => Box::new(PdcchSchedShuffling::with_displacement(
+                count_table,
+                start_position,
+                Displacement {
+                    depth: args.shuffle_depth,
+                    ..Default::default()
+                },
+            )),
+            AllocatorType::Optimal => Box::new(PdcchSchedOptimal::new(count_table)),
+        };
+        allocator.set_budget(Budget {
+            max_steps: args.budget_steps,
+            time: args.budget_us.map(Duration::from_micros),
+        });
+        allocator.set_cfi_policy(args.cfi_policy.policy(args.cfi));
+        allocator
+    }
+}
+
+/// to run: cargo run -r -- --test N --prb M (6, 100 and several others) [--allocator tree|beam|sequential|shuffling|optimal] [--beam-width K] [--shuffle-depth D] [--budget-us T] [--budget-steps N] [--cfi-policy minimize|fixed|cap|last-tti] [--cfi C] [--cqi] [--seed S]
+/// test 5 compares all allocators on the same DCI load
 pub fn main() {
     let args = Args::parse();
 
+    let mut cell_config = init::cell_cfg(&SchedulerConfig::default());
+    cell_config.nof_prb = args.prb;
+
+    let count_table = resource_allocation::calculate_cce_count_table(&cell_config);
+    let mut s = args.allocator.create(count_table, &args);
+    let mut rng = match args.seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    };
+    let mut levels = if args.cqi {
+        LevelSource::Cqi(CqiLevels {
+            link_model: PdcchLinkModel::new(&cell_config, TARGET_BLER),
+            payload_bits: DciSizes::new(&cell_config).payload_bits(DciFormat::F1A),
+            cfi: cfi_range(&count_table).1,
+            outer_loop: OuterLoop::new(TARGET_BLER),
+            sent: 0,
+            missed: 0,
+        })
+    } else {
+        LevelSource::Fixed
+    };
+
     match args.test {
-        1 => pdcch_allocate_test(args.prb),
-        2 => pdcch_cnt_free_cce_test(args.prb),
-        3 => pdcch_time_test(args.prb),
-        0 => test(args.prb),
+        1 => pdcch_allocate_test(s.as_mut(), &count_table, &mut levels, &mut rng),
+        2 => pdcch_cnt_free_cce_test(s.as_mut(), &count_table, args.prb, &mut levels, &mut rng),
+        3 => pdcch_time_test(s.as_mut(), &count_table, &mut levels, &mut rng),
+        4 => print_searsc_spaces(Rnti(rng.gen())),
+        5 => pdcch_compare_test(&count_table, &args, &mut levels, &mut rng),
+        0 => test(s.as_mut(), &count_table),
         _ => println!("Incorrect input"),
     }
+    levels.print_summary();
 }
 
 fn print_searsc_spaces(rnti: Rnti) {
@@ -42,28 +254,19 @@ fn print_searsc_spaces(rnti: Rnti) {
     let tti = 1;
     let cfi = 0;
 
-    for aggregation_level in 0..4 {
+    for aggregation_level in PdcchAggregation::list() {
         let mut alloc_mask = CceMask::new(count_table[cfi] as usize);
-        for start_cce in &search_space[tti][cfi][aggregation_level] {
-            //  println!("mask size {}, start cce {}, len {}", alloc_mask.size(), start_cce, 2_u32.pow(aggregation_level as u32));
+        for start_cce in &search_space[tti][cfi][aggregation_level.index()] {
+            //  println!("mask size {}, start cce {}, len {}", alloc_mask.size(), start_cce, aggregation_level.size());
             alloc_mask
-                .fill(
-                    *start_cce as usize,
-                    2_u32.pow(aggregation_level as u32) as usize,
-                    true,
-                )
+                .fill(*start_cce as usize, aggregation_level.size(), true)
                 .unwrap();
         }
         println!("{}", alloc_mask);
     }
 }
 
-fn test(prb_count: u8) {
-    let mut cell_config = init::cell_cfg(&SchedulerConfig::default());
-    cell_config.nof_prb = prb_count;
-
-    let count_table = resource_allocation::calculate_cce_count_table(&cell_config);
-    let mut s = PdcchSched::new(count_table);
+fn test(s: &mut dyn PdcchAllocator, count_table: &CceCountTable) {
     // Randomly generated values
     let data = [
         (PdcchAggregation::L1, Rnti(10249)),
@@ -78,14 +281,11 @@ fn test(prb_count: u8) {
         // (PdcchAggregation::L2, Rnti(1403)),
     ];
 
-    let dci_count = data.len();
-
     s.new_tti();
     let tti = TtiPoint::from(1);
 
-    for i in 0..dci_count {
-        let (aggregation_level, rnti) = data[i];
-        let search_space = calculate_search_space(&rnti, &count_table);
+    for &(aggregation_level, rnti) in data.iter() {
+        let search_space = calculate_search_space(&rnti, count_table);
 
         let _ = s.allocate_dci(aggregation_level, &search_space[tti.to_usize() % 10], rnti);
     }
@@ -97,15 +297,12 @@ fn test(prb_count: u8) {
 }
 
 /// Dependence of the number of allocated DCI on the number of requested
-fn pdcch_allocate_test(prb_count: u8) {
-    let mut rng = rand::thread_rng();
-
-    let mut cell_config = init::cell_cfg(&SchedulerConfig::default());
-    cell_config.nof_prb = prb_count;
-
-    let count_table = resource_allocation::calculate_cce_count_table(&cell_config);
-    let mut s = PdcchSched::new(count_table);
-
+fn pdcch_allocate_test(
+    s: &mut dyn PdcchAllocator,
+    count_table: &CceCountTable,
+    levels: &mut LevelSource,
+    rng: &mut StdRng,
+) {
     for dci_count in 1..=20 {
         let mut ok_count: u64 = 0;
 
@@ -115,9 +312,9 @@ fn pdcch_allocate_test(prb_count: u8) {
 
             for _ in 0..dci_count {
                 let rnti = Rnti(rng.gen());
-                let search_space = calculate_search_space(&rnti, &count_table);
+                let search_space = calculate_search_space(&rnti, count_table);
 
-                let aggregation_level = gen_aggregation_level(&mut rng);
+                let aggregation_level = levels.gen(rng);
 
                 let res =
                     s.allocate_dci(aggregation_level, &search_space[tti.to_usize() % 10], rnti);
@@ -132,19 +329,18 @@ fn pdcch_allocate_test(prb_count: u8) {
 }
 
 /// Dependence of the number of unoccupied CCE on the number of requested DCI
-fn pdcch_cnt_free_cce_test(prb_count: u8) {
-    let mut rng = rand::thread_rng();
-
-    let mut cell_config = init::cell_cfg(&SchedulerConfig::default());
-    cell_config.nof_prb = prb_count;
+fn pdcch_cnt_free_cce_test(
+    s: &mut dyn PdcchAllocator,
+    count_table: &CceCountTable,
+    prb_count: u8,
+    levels: &mut LevelSource,
+    rng: &mut StdRng,
+) {
     let max_dci_cnt = match prb_count {
         6 => 6,
         _ => 8,
     };
 
-    let count_table = resource_allocation::calculate_cce_count_table(&cell_config);
-    let mut s = PdcchSched::new(count_table);
-
     for dci_count in 1..=max_dci_cnt {
         let mut counter = 0;
         let mut free_cce = 0;
@@ -157,9 +353,9 @@ fn pdcch_cnt_free_cce_test(prb_count: u8) {
 
             for _ in 0..dci_count {
                 let rnti = Rnti(rng.gen());
-                let search_space = calculate_search_space(&rnti, &count_table);
+                let search_space = calculate_search_space(&rnti, count_table);
 
-                let aggregation_level = gen_aggregation_level(&mut rng);
+                let aggregation_level = levels.gen(rng);
 
                 let res =
                     s.allocate_dci(aggregation_level, &search_space[tti.to_usize() % 10], rnti);
@@ -180,22 +376,19 @@ fn pdcch_cnt_free_cce_test(prb_count: u8) {
 }
 
 /// Dependence of operating time on the number of requested DCI
-fn pdcch_time_test(prb_count: u8) {
-    let mut rng = rand::thread_rng();
-
-    let mut cell_config = init::cell_cfg(&SchedulerConfig::default());
-    cell_config.nof_prb = prb_count;
-
-    let count_table = resource_allocation::calculate_cce_count_table(&cell_config);
-    let mut s = PdcchSched::new(count_table);
-
+fn pdcch_time_test(
+    s: &mut dyn PdcchAllocator,
+    count_table: &CceCountTable,
+    levels: &mut LevelSource,
+    rng: &mut StdRng,
+) {
     let mut precalculated_values = Vec::new();
     for _ in 0..64_000 {
         let rnti = Rnti(rng.gen());
 
-        let search_space = calculate_search_space(&rnti, &count_table);
+        let search_space = calculate_search_space(&rnti, count_table);
 
-        let aggregation_level = gen_aggregation_level(&mut rng);
+        let aggregation_level = levels.gen(rng);
 
         precalculated_values.push((aggregation_level, search_space, rnti));
     }
@@ -224,9 +417,99 @@ fn pdcch_time_test(prb_count: u8) {
     }
 }
 
-fn gen_aggregation_level(rng: &mut ThreadRng) -> PdcchAggregation {
+/// DCIs requested in one TTI
+type TtiLoad = (TtiPoint, Vec<(PdcchAggregation, SearchSpace, Rnti)>);
+
+/// Same random DCI load through all allocators: allocated DCI, unoccupied CCE and time per TTI
+fn pdcch_compare_test(
+    count_table: &CceCountTable,
+    args: &Args,
+    levels: &mut LevelSource,
+    rng: &mut StdRng,
+) {
+    let allocator_types = AllocatorType::value_variants();
+    let mut allocators: Vec<Box<dyn PdcchAllocator>> = allocator_types
+        .iter()
+        .map(|allocator_type| allocator_type.create(*count_table, args))
+        .collect();
+
+    let mut header = String::from("dci");
+    for column in ["allocated", "free cce", "time", "exhaustive"] {
+        for allocator_type in allocator_types {
+            header += &format!(";{} {}", allocator_type.name(), column);
+        }
+    }
+    println!("{}", header);
+
+    for dci_count in 1..=8 {
+        let mut load: Vec<TtiLoad> = Vec::new();
+        for _ in 0..1_000 {
+            let tti = TtiPoint::from(rng.gen::<u16>());
+            let dcis = (0..dci_count)
+                .map(|_| {
+                    let rnti = Rnti(rng.gen());
+                    let search_space = calculate_search_space(&rnti, count_table);
+                    (levels.gen(rng), search_space, rnti)
+                })
+                .collect();
+            load.push((tti, dcis));
+        }
+
+        let mut ok_counts = Vec::new();
+        let mut free_cces = Vec::new();
+        let mut durations = Vec::new();
+        let mut exhaustive_shares = Vec::new();
+        for s in allocators.iter_mut() {
+            let mut ok_count: u64 = 0;
+            let mut free_cce = 0;
+            let mut exhaustive_count = 0;
+            let start_time = Instant::now();
+
+            for (tti, dcis) in load.iter() {
+                s.new_tti();
+                for (aggregation_level, search_space, rnti) in dcis {
+                    let res = s.allocate_dci(
+                        *aggregation_level,
+                        &search_space[tti.to_usize() % 10],
+                        *rnti,
+                    );
+                    if res.is_ok() {
+                        ok_count += 1;
+                    }
+                }
+                let (_, mask, _) = s.get_allocs();
+                free_cce += mask.size() - mask.count();
+                if s.exhaustive() {
+                    exhaustive_count += 1;
+                }
+            }
+
+            durations.push(start_time.elapsed() / 1_000);
+            exhaustive_shares.push(exhaustive_count as f64 / 1_000.0);
+            ok_counts.push(ok_count as f64 / 1_000.0);
+            free_cces.push(free_cce as f64 / 1_000.0);
+        }
+
+        let mut f = format!("{}", dci_count);
+        for ok_count in ok_counts {
+            f += &format!(";{}", ok_count);
+        }
+        for free_cce in free_cces {
+            f += &format!(";{}", free_cce);
+        }
+        for duration in durations {
+            f += &format!(";{:?}", duration);
+        }
+        for exhaustive_share in exhaustive_shares {
+            f += &format!(";{}", exhaustive_share);
+        }
+        println!("{}", f.replace(".", ","));
+    }
+}
+
+fn gen_aggregation_level(rng: &mut StdRng) -> PdcchAggregation {
     let p: f64 = rng.gen();
-    let aggregation_level = if p < 0.6 {
+    if p < 0.6 {
         PdcchAggregation::L1
     } else if p < 0.8 {
         PdcchAggregation::L2
@@ -234,6 +517,5 @@ fn gen_aggregation_level(rng: &mut ThreadRng) -> PdcchAggregation {
         PdcchAggregation::L4
     } else {
         PdcchAggregation::L8
-    };
-    aggregation_level
+    }
 }
@@ -0,0 +1,252 @@
+use crate::allocator::structures::{CceCountTable, PdschReTable};
+use crate::defines;
+use crate::scheduler_config::{CellConfig, CyclicPrefix, Duplex};
+use crate::types::cfi::Cfi;
+
+/// REGs per PRB in OFDM symbols with CRS (4 of 6 REs in each REG carry data)
+const REG_PER_PRB_CRS_SYMBOL: usize = 2;
+/// REGs per PRB in OFDM symbols without CRS
+const REG_PER_PRB_SYMBOL: usize = 3;
+/// Subcarriers in one PRB
+const SUBCARRIERS_PER_PRB: u16 = 12;
+/// REs of CRS in one PRB in OFDM symbols with CRS
+const CRS_RE_PER_PRB_SYMBOL: u16 = 4;
+/// REGs in one PHICH mapping unit
+const PHICH_MAPPING_UNIT_REG_COUNT: usize = 3;
+/// Cells up to this bandwidth use 2..4 OFDM symbols for PDCCH, wider cells use 1..3 (36.211 table 6.7-1)
+const NARROW_BANDWIDTH_MAX_PRB: u8 = 10;
+
+/// Factor m_i for PHICH groups in TDD (36.211 table 6.9-1). Uplink subframes carry no PHICH
+const TDD_PHICH_M_I: [[u8; defines::NUM_OF_SF]; 7] = [
+    [2, 1, 0, 0, 0, 2, 1, 0, 0, 0],
+    [0, 1, 0, 0, 1, 0, 1, 0, 0, 1],
+    [0, 0, 0, 1, 0, 0, 0, 0, 1, 0],
+    [1, 0, 0, 0, 0, 0, 0, 0, 1, 1],
+    [0, 0, 0, 0, 0, 0, 0, 0, 1, 1],
+    [0, 0, 0, 0, 0, 0, 0, 0, 1, 0],
+    [1, 1, 0, 0, 0, 1, 1, 0, 0, 1],
+];
+
+/// Number of CCEs available for PDCCH for each CFI in subframe 0.
+/// For FDD it is the same for every subframe, for TDD see `calculate_sf_cce_count_table`
+pub fn calculate_cce_count_table(cell_config: &CellConfig) -> CceCountTable {
+    calculate_sf_cce_count_table(cell_config, 0)
+}
+
+/// Number of CCEs available for PDCCH for each CFI in the given subframe
+pub fn calculate_sf_cce_count_table(cell_config: &CellConfig, subframe: usize) -> CceCountTable {
+    let mut table = CceCountTable::default();
+    for cfi in Cfi::list() {
+        table[cfi.index()] = calculate_cce_count(cell_config, cfi.value(), subframe);
+    }
+    table
+}
+
+/// PDSCH REs in one PRB pair for each CFI in subframe 0, see `calculate_pdsch_re_count`
+pub fn calculate_pdsch_re_table(cell_config: &CellConfig) -> PdschReTable {
+    let mut table = PdschReTable::default();
+    for cfi in Cfi::list() {
+        if cfi.pcfich_value(cell_config.nof_prb).is_some() {
+            table[cfi.index()] = calculate_pdsch_re_count(cell_config, cfi.value());
+        }
+    }
+    table
+}
+
+/// REs of one PRB pair after control region of `nof_symbols` OFDM symbols without CRS
+/// (36.211 6.10.1). PBCH, synchronization signals and UE-specific RS are not subtracted
+pub fn calculate_pdsch_re_count(cell_config: &CellConfig, nof_symbols: u8) -> u16 {
+    let symbols_per_slot = match cell_config.cyclic_prefix {
+        CyclicPrefix::Normal => 7,
+        CyclicPrefix::Extended => 6,
+    };
+    (nof_symbols..2 * symbols_per_slot)
+        .map(|symbol| {
+            let symbol = symbol % symbols_per_slot;
+            // Ports 0 and 1 in symbols 0 and N_symb - 3, ports 2 and 3 in symbol 1
+            let has_crs = symbol == 0
+                || symbol == symbols_per_slot - 3
+                || (symbol == 1 && cell_config.nof_ports == 4);
+            if has_crs {
+                SUBCARRIERS_PER_PRB - CRS_RE_PER_PRB_SYMBOL
+            } else {
+                SUBCARRIERS_PER_PRB
+            }
+        })
+        .sum()
+}
+
+/// N_CCE for control region of `nof_symbols` OFDM symbols (36.211 6.2.4, 6.7-6.9, 36.213 9.1.1):
+/// REGs in control region without PCFICH and PHICH REGs, divided by 9.
+/// Returns 0 if the cell bandwidth doesn't allow such control region
+pub fn calculate_cce_count(cell_config: &CellConfig, nof_symbols: u8, subframe: usize) -> u8 {
+    let allowed_symbols = if cell_config.nof_prb <= NARROW_BANDWIDTH_MAX_PRB {
+        2..=4
+    } else {
+        1..=3
+    };
+    if !allowed_symbols.contains(&nof_symbols) {
+        return 0;
+    }
+
+    let nof_prb = cell_config.nof_prb as usize;
+    let reg_count: usize = (0..nof_symbols)
+        .map(|symbol| nof_prb * reg_per_prb(cell_config, symbol))
+        .sum();
+
+    let reserved_reg = defines::PCFICH_REG_COUNT
+        + phich_mapping_unit_count(cell_config, subframe) * PHICH_MAPPING_UNIT_REG_COUNT;
+
+    let cce_count = reg_count.saturating_sub(reserved_reg) / defines::REG_PER_CCE;
+    cce_count.min(defines::NUM_OF_CCES) as u8
+}
+
+/// REGs per PRB in OFDM symbol `symbol` of the control region (36.211 6.2.4)
+fn reg_per_prb(cell_config: &CellConfig, symbol: u8) -> usize {
+    let has_crs = match symbol {
+        // Ports 0 and 1 are assumed even for one port
+        0 => true,
+        1 => cell_config.nof_ports == 4,
+        3 => cell_config.cyclic_prefix == CyclicPrefix::Extended,
+        _ => false,
+    };
+    if has_crs {
+        REG_PER_PRB_CRS_SYMBOL
+    } else {
+        REG_PER_PRB_SYMBOL
+    }
+}
+
+/// Number of PHICH mapping units (36.211 6.9, 6.9.3).
+/// With extended CP there are twice as many groups, but two groups share one mapping unit
+fn phich_mapping_unit_count(cell_config: &CellConfig, subframe: usize) -> usize {
+    let (numerator, denominator) = cell_config.phich_resource.ratio();
+    // N_group = ceil(Ng * (N_RB / 8))
+    let group_count = (numerator * cell_config.nof_prb as usize).div_ceil(denominator * 8);
+
+    let m_i = match cell_config.duplex {
+        Duplex::Fdd => 1,
+        Duplex::Tdd { ul_dl_config } => {
+            TDD_PHICH_M_I[ul_dl_config as usize][subframe % defines::NUM_OF_SF] as usize
+        }
+    };
+
+    m_i * group_count
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::allocator::test_fixture::cell;
+    use crate::scheduler_config::PhichResource;
+
+    /// N_CCE for 1, 2, 3 and 4 OFDM symbols
+    fn cce_counts(cell_config: &CellConfig, subframe: usize) -> [u8; 4] {
+        [1, 2, 3, 4].map(|nof_symbols| calculate_cce_count(cell_config, nof_symbols, subframe))
+    }
+
+    #[test]
+    fn default_cell() {
+        // Normal CP, 2 ports, Ng = 1, FDD
+        let expected = [
+            (6, [0, 2, 4, 6]),
+            (15, [2, 7, 12, 0]),
+            (25, [3, 12, 20, 0]),
+            (50, [8, 25, 41, 0]),
+            (75, [12, 37, 62, 0]),
+            (100, [17, 50, 84, 0]),
+        ];
+        for (nof_prb, cce_count) in expected {
+            assert_eq!(cce_counts(&cell(nof_prb), 0), cce_count, "{} PRB", nof_prb);
+        }
+        assert_eq!(calculate_cce_count_table(&cell(100)), [17, 50, 84, 0]);
+        assert_eq!(calculate_cce_count_table(&cell(6)), [0, 2, 4, 6]);
+    }
+
+    #[test]
+    fn four_ports() {
+        let expected = [
+            (6, [0, 1, 3, 5]),
+            (25, [3, 9, 17, 0]),
+            (100, [17, 39, 73, 0]),
+        ];
+        for (nof_prb, cce_count) in expected {
+            let mut cell_config = cell(nof_prb);
+            cell_config.nof_ports = 4;
+            assert_eq!(cce_counts(&cell_config, 0), cce_count, "{} PRB", nof_prb);
+        }
+
+        // One port uses the same REG layout as two
+        let mut cell_config = cell(100);
+        cell_config.nof_ports = 1;
+        assert_eq!(cce_counts(&cell_config, 0), [17, 50, 84, 0]);
+    }
+
+    #[test]
+    fn phich_resource() {
+        let expected = [
+            (PhichResource::OneSixth, [20, 54, 87, 0]),
+            (PhichResource::Half, [19, 52, 86, 0]),
+            (PhichResource::One, [17, 50, 84, 0]),
+            (PhichResource::Two, [13, 46, 80, 0]),
+        ];
+        for (phich_resource, cce_count) in expected {
+            let mut cell_config = cell(100);
+            cell_config.phich_resource = phich_resource;
+            assert_eq!(
+                cce_counts(&cell_config, 0),
+                cce_count,
+                "{:?}",
+                phich_resource
+            );
+        }
+    }
+
+    #[test]
+    fn extended_cyclic_prefix() {
+        // CRS in the fourth symbol
+        let mut cell_config = cell(6);
+        cell_config.cyclic_prefix = CyclicPrefix::Extended;
+        assert_eq!(cce_counts(&cell_config, 0), [0, 2, 4, 5]);
+
+        let mut cell_config = cell(100);
+        cell_config.cyclic_prefix = CyclicPrefix::Extended;
+        assert_eq!(cce_counts(&cell_config, 0), [17, 50, 84, 0]);
+    }
+
+    #[test]
+    fn pdsch_re() {
+        assert_eq!(calculate_pdsch_re_table(&cell(100)), [144, 132, 120, 0]);
+        assert_eq!(calculate_pdsch_re_table(&cell(6)), [0, 132, 120, 108]);
+
+        let mut cell_config = cell(100);
+        cell_config.nof_ports = 4;
+        assert_eq!(calculate_pdsch_re_table(&cell_config), [136, 128, 116, 0]);
+
+        let mut cell_config = cell(100);
+        cell_config.cyclic_prefix = CyclicPrefix::Extended;
+        assert_eq!(calculate_pdsch_re_table(&cell_config), [120, 108, 96, 0]);
+    }
+
+    #[test]
+    fn tdd_phich_m_i() {
+        let mut cell_config = cell(100);
+        cell_config.duplex = Duplex::Tdd { ul_dl_config: 0 };
+
+        // m_i = 2
+        assert_eq!(
+            calculate_sf_cce_count_table(&cell_config, 0),
+            [13, 46, 79, 0]
+        );
+        // m_i = 1
+        assert_eq!(
+            calculate_sf_cce_count_table(&cell_config, 1),
+            [17, 50, 84, 0]
+        );
+        // m_i = 0
+        assert_eq!(
+            calculate_sf_cce_count_table(&cell_config, 2),
+            [21, 55, 88, 0]
+        );
+    }
+}
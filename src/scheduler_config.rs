@@ -0,0 +1,70 @@
+/// Static scheduler configuration
+#[derive(Clone, Debug)]
+pub struct SchedulerConfig {
+    /// Cell bandwidth in PRB (6, 15, 25, 50, 75, 100)
+    pub nof_prb: u8,
+    pub phich_resource: PhichResource,
+    /// Number of cell-specific reference signal antenna ports (1, 2 or 4)
+    pub nof_ports: u8,
+    pub cyclic_prefix: CyclicPrefix,
+    pub duplex: Duplex,
+}
+
+impl Default for SchedulerConfig {
+    fn default() -> Self {
+        SchedulerConfig {
+            nof_prb: 25,
+            phich_resource: PhichResource::One,
+            nof_ports: 2,
+            cyclic_prefix: CyclicPrefix::Normal,
+            duplex: Duplex::Fdd,
+        }
+    }
+}
+
+/// Cell parameters used by the scheduler at runtime
+#[derive(Clone, Debug)]
+pub struct CellConfig {
+    pub nof_prb: u8,
+    pub phich_resource: PhichResource,
+    pub nof_ports: u8,
+    pub cyclic_prefix: CyclicPrefix,
+    pub duplex: Duplex,
+}
+
+/// PHICH resource parameter Ng (36.211 6.9)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PhichResource {
+    OneSixth,
+    Half,
+    #[default]
+    One,
+    Two,
+}
+
+impl PhichResource {
+    /// Ng as a fraction (numerator, denominator)
+    pub const fn ratio(&self) -> (usize, usize) {
+        match self {
+            PhichResource::OneSixth => (1, 6),
+            PhichResource::Half => (1, 2),
+            PhichResource::One => (1, 1),
+            PhichResource::Two => (2, 1),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CyclicPrefix {
+    #[default]
+    Normal,
+    Extended,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Duplex {
+    #[default]
+    Fdd,
+    /// Uplink-downlink configuration 0..6
+    Tdd { ul_dl_config: u8 },
+}
@@ -0,0 +1,2 @@
+pub mod sched_pdcch;
+pub mod structs;
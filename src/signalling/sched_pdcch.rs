@@ -0,0 +1,6 @@
+/*
+Path under which the MAC scheduler uses the PDCCH allocator
+*/
+pub use crate::allocator::pdcch_allocator::PdcchAllocator;
+pub use crate::allocator::structures::*;
+pub use crate::allocator::tree_pdcch_allocator::{PdcchSched, PdcchSchedTree};
@@ -0,0 +1,29 @@
+use std::fmt;
+
+/// Reasons why resources could not be allocated
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AllocationError {
+    /// No free CCE left in PDCCH
+    NoCchSpace,
+    /// No free PRB left in PDSCH/PUSCH
+    NoDataSpace,
+    /// Input parameters are out of allowed range
+    InvalidParameter,
+    /// DCI format does not fit into the aggregation level with allowed coding rate
+    CodingRateTooHigh,
+}
+
+impl fmt::Display for AllocationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AllocationError::NoCchSpace => write!(f, "no space in control channel"),
+            AllocationError::NoDataSpace => write!(f, "no space in data channel"),
+            AllocationError::InvalidParameter => write!(f, "invalid parameter"),
+            AllocationError::CodingRateTooHigh => write!(f, "coding rate too high"),
+        }
+    }
+}
+
+impl std::error::Error for AllocationError {}
+
+pub type AllocationResult<T = ()> = Result<T, AllocationError>;
@@ -0,0 +1,191 @@
+use std::fmt;
+use std::ops::{BitAnd, BitAndAssign, BitOr, BitOrAssign, BitXor, Not};
+
+/// Bitset with runtime size limited by `N` bits. Bits beyond `size` are always zero.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct BoundedBitset<const N: usize> {
+    size: usize,
+    bits: u128,
+}
+
+#[allow(clippy::result_unit_err)]
+impl<const N: usize> BoundedBitset<N> {
+    const CAPACITY_CHECK: () = assert!(
+        N <= u128::BITS as usize,
+        "BoundedBitset supports up to 128 bits"
+    );
+
+    /// Bits at multiples of 1, 2, 4, ..., 64
+    const BLOCK_STARTS: [u128; 7] = [
+        u128::MAX,
+        u128::MAX / 0x3,
+        u128::MAX / 0xf,
+        u128::MAX / 0xff,
+        u128::MAX / 0xffff,
+        u128::MAX / 0xffff_ffff,
+        u128::MAX / 0xffff_ffff_ffff_ffff,
+    ];
+
+    pub fn new(size: usize) -> Self {
+        #[allow(clippy::let_unit_value)]
+        let _ = Self::CAPACITY_CHECK;
+        assert!(size <= N, "bitset size {} exceeds capacity {}", size, N);
+        BoundedBitset { size, bits: 0 }
+    }
+
+    pub const fn capacity() -> usize {
+        N
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Number of set bits
+    pub fn count(&self) -> usize {
+        self.bits.count_ones() as usize
+    }
+
+    pub fn any(&self) -> bool {
+        self.bits != 0
+    }
+
+    pub fn none(&self) -> bool {
+        self.bits == 0
+    }
+
+    pub fn all(&self) -> bool {
+        self.count() == self.size
+    }
+
+    pub fn test(&self, idx: usize) -> bool {
+        idx < self.size && (self.bits >> idx) & 1 == 1
+    }
+
+    pub fn set(&mut self, idx: usize, value: bool) -> Result<(), ()> {
+        self.fill(idx, 1, value)
+    }
+
+    /// Sets `len` bits starting from `start` to `value`
+    pub fn fill(&mut self, start: usize, len: usize, value: bool) -> Result<(), ()> {
+        if start + len > self.size {
+            return Err(());
+        }
+        let mask = Self::range_mask(start, len);
+        if value {
+            self.bits |= mask;
+        } else {
+            self.bits &= !mask;
+        }
+        Ok(())
+    }
+
+    pub fn reset(&mut self) {
+        self.bits = 0;
+    }
+
+    /// Indices of set bits in ascending order
+    pub fn iter_ones(&self) -> impl Iterator<Item = usize> {
+        let bits = self.bits;
+        (0..self.size).filter(move |i| (bits >> i) & 1 == 1)
+    }
+
+    /// Number of clear blocks of `len` bits starting at multiples of `len`, `len` is a power of two
+    pub fn count_clear_blocks(&self, len: usize) -> usize {
+        assert!(
+            len.is_power_of_two() && len < u128::BITS as usize,
+            "block length {} is not a power of two below 128",
+            len
+        );
+        // Bit i of `clear` tells that bits i..i + len are clear and below `size`
+        let mut clear = !self.bits & Self::range_mask(0, self.size);
+        let mut shift = 1;
+        while shift < len {
+            clear &= clear >> shift;
+            shift *= 2;
+        }
+        (clear & Self::BLOCK_STARTS[len.trailing_zeros() as usize]).count_ones() as usize
+    }
+
+    fn range_mask(start: usize, len: usize) -> u128 {
+        if len == 0 {
+            0
+        } else if len >= u128::BITS as usize {
+            u128::MAX
+        } else {
+            ((1u128 << len) - 1) << start
+        }
+    }
+}
+
+impl<const N: usize> BitAnd for BoundedBitset<N> {
+    type Output = Self;
+
+    fn bitand(self, rhs: Self) -> Self {
+        BoundedBitset {
+            size: self.size.max(rhs.size),
+            bits: self.bits & rhs.bits,
+        }
+    }
+}
+
+impl<const N: usize> BitOr for BoundedBitset<N> {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        BoundedBitset {
+            size: self.size.max(rhs.size),
+            bits: self.bits | rhs.bits,
+        }
+    }
+}
+
+impl<const N: usize> BitXor for BoundedBitset<N> {
+    type Output = Self;
+
+    fn bitxor(self, rhs: Self) -> Self {
+        BoundedBitset {
+            size: self.size.max(rhs.size),
+            bits: self.bits ^ rhs.bits,
+        }
+    }
+}
+
+impl<const N: usize> BitAndAssign for BoundedBitset<N> {
+    fn bitand_assign(&mut self, rhs: Self) {
+        *self = *self & rhs;
+    }
+}
+
+impl<const N: usize> BitOrAssign for BoundedBitset<N> {
+    fn bitor_assign(&mut self, rhs: Self) {
+        *self = *self | rhs;
+    }
+}
+
+impl<const N: usize> Not for BoundedBitset<N> {
+    type Output = Self;
+
+    fn not(self) -> Self {
+        BoundedBitset {
+            size: self.size,
+            bits: !self.bits & Self::range_mask(0, self.size),
+        }
+    }
+}
+
+/// Prints bits from index 0 to `size - 1`
+impl<const N: usize> fmt::Display for BoundedBitset<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for i in 0..self.size {
+            write!(f, "{}", if self.test(i) { '1' } else { '0' })?;
+        }
+        Ok(())
+    }
+}
+
+impl<const N: usize> fmt::Debug for BoundedBitset<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "BoundedBitset({})", self)
+    }
+}
@@ -0,0 +1,106 @@
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+
+/// Heap vector with max length `N`. Memory is allocated on demand, not up to capacity.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct BoundedVec<T, const N: usize> {
+    data: Vec<T>,
+}
+
+impl<T, const N: usize> BoundedVec<T, N> {
+    pub const fn new() -> Self {
+        BoundedVec { data: Vec::new() }
+    }
+
+    pub const fn capacity() -> usize {
+        N
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.data.len() >= N
+    }
+
+    /// Appends an element. Returns it back if vector is full
+    pub fn push(&mut self, value: T) -> Result<(), T> {
+        if self.is_full() {
+            return Err(value);
+        }
+        self.data.push(value);
+        Ok(())
+    }
+
+    pub fn pop(&mut self) -> Option<T> {
+        self.data.pop()
+    }
+
+    pub fn remove(&mut self, index: usize) -> T {
+        self.data.remove(index)
+    }
+
+    pub fn truncate(&mut self, len: usize) {
+        self.data.truncate(len);
+    }
+
+    pub fn retain<F: FnMut(&T) -> bool>(&mut self, f: F) {
+        self.data.retain(f);
+    }
+
+    pub fn clear(&mut self) {
+        self.data.clear();
+    }
+}
+
+impl<T, const N: usize> Default for BoundedVec<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: fmt::Debug, const N: usize> fmt::Debug for BoundedVec<T, N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.data.iter()).finish()
+    }
+}
+
+impl<T, const N: usize> Deref for BoundedVec<T, N> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        &self.data
+    }
+}
+
+impl<T, const N: usize> DerefMut for BoundedVec<T, N> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        &mut self.data
+    }
+}
+
+impl<'a, T, const N: usize> IntoIterator for &'a BoundedVec<T, N> {
+    type Item = &'a T;
+    type IntoIter = std::slice::Iter<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.data.iter()
+    }
+}
+
+impl<T, const N: usize> IntoIterator for BoundedVec<T, N> {
+    type Item = T;
+    type IntoIter = std::vec::IntoIter<T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.data.into_iter()
+    }
+}
+
+impl<T, const N: usize> TryFrom<Vec<T>> for BoundedVec<T, N> {
+    type Error = Vec<T>;
+
+    fn try_from(data: Vec<T>) -> Result<Self, Vec<T>> {
+        if data.len() > N {
+            return Err(data);
+        }
+        Ok(BoundedVec { data })
+    }
+}
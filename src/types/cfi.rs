@@ -0,0 +1,62 @@
+use crate::defines;
+
+/// Control format indicator as number of OFDM symbols used by PDCCH.
+/// Cells up to 10 PRB use 2..4 symbols, wider cells use 1..3 (36.211 table 6.7-1)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[repr(u8)]
+pub enum Cfi {
+    #[default]
+    One = 1,
+    Two,
+    Three,
+    Four,
+}
+
+impl Cfi {
+    pub const fn value(&self) -> u8 {
+        *self as u8
+    }
+
+    /// Index in per-CFI tables
+    pub const fn index(&self) -> usize {
+        *self as usize - 1
+    }
+
+    pub const fn from_index(index: usize) -> Option<Cfi> {
+        match index {
+            0 => Some(Cfi::One),
+            1 => Some(Cfi::Two),
+            2 => Some(Cfi::Three),
+            3 => Some(Cfi::Four),
+            _ => None,
+        }
+    }
+
+    pub const fn list() -> [Cfi; defines::NUM_OF_CFI] {
+        [Cfi::One, Cfi::Two, Cfi::Three, Cfi::Four]
+    }
+
+    /// Value transmitted on PCFICH (36.211 table 6.7-1), `None` if the cell can't use this CFI
+    pub const fn pcfich_value(&self, nof_prb: u8) -> Option<u8> {
+        match (nof_prb <= 10, self) {
+            (true, Cfi::One) | (false, Cfi::Four) => None,
+            (true, _) => Some(self.value() - 1),
+            (false, _) => Some(self.value()),
+        }
+    }
+
+    /// Next bigger CFI, if any
+    pub const fn next(&self) -> Option<Cfi> {
+        Cfi::from_index(self.index() + 1)
+    }
+
+    /// Iterates from this CFI up to the biggest one
+    pub fn iter(&self) -> impl Iterator<Item = Cfi> {
+        Cfi::list().into_iter().skip(self.index())
+    }
+
+    /// Iterates from this CFI up to `last` inclusive
+    pub fn iter_to(&self, last: Cfi) -> impl Iterator<Item = Cfi> {
+        self.iter().take_while(move |cfi| *cfi <= last)
+    }
+}
@@ -0,0 +1,34 @@
+use std::ops::Range;
+
+/// Half-open interval `[start, end)` with `end` limited by `MAX`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Interval<T, const MAX: usize> {
+    pub start: T,
+    pub end: T,
+}
+
+#[allow(clippy::result_unit_err)]
+impl<const MAX: usize> Interval<usize, MAX> {
+    pub fn new(start: usize, end: usize) -> Result<Self, ()> {
+        if start > end || end > MAX {
+            return Err(());
+        }
+        Ok(Interval { start, end })
+    }
+
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    pub fn contains(&self, value: usize) -> bool {
+        self.start <= value && value < self.end
+    }
+
+    pub fn range(&self) -> Range<usize> {
+        self.start..self.end
+    }
+}
@@ -0,0 +1,6 @@
+pub mod bitset;
+pub mod bounded_vec;
+pub mod cfi;
+pub mod interval;
+pub mod rnti;
+pub mod tti;
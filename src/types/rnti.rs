@@ -0,0 +1,25 @@
+use std::fmt;
+use std::ops::Deref;
+
+/// Radio network temporary identifier
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Rnti(pub u16);
+
+impl Rnti {
+    pub const SI_RNTI: Rnti = Rnti(0xFFFF);
+    pub const P_RNTI: Rnti = Rnti(0xFFFE);
+}
+
+impl Deref for Rnti {
+    type Target = u16;
+
+    fn deref(&self) -> &u16 {
+        &self.0
+    }
+}
+
+impl fmt::Display for Rnti {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "0x{:04x}", self.0)
+    }
+}
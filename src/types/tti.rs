@@ -0,0 +1,36 @@
+use crate::defines;
+use std::ops::AddAssign;
+
+/// Number of TTIs in one SFN cycle (1024 frames)
+const TTI_PERIOD: u32 = 1024 * defines::NUM_OF_SF as u32;
+
+/// TTI counter wrapping over the SFN cycle
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TtiPoint(u32);
+
+impl TtiPoint {
+    pub fn to_usize(&self) -> usize {
+        self.0 as usize
+    }
+
+    /// Subframe index inside the radio frame
+    pub fn sf_idx(&self) -> usize {
+        self.0 as usize % defines::NUM_OF_SF
+    }
+
+    pub fn sfn(&self) -> u32 {
+        self.0 / defines::NUM_OF_SF as u32
+    }
+}
+
+impl From<u16> for TtiPoint {
+    fn from(tti: u16) -> Self {
+        TtiPoint(tti as u32 % TTI_PERIOD)
+    }
+}
+
+impl AddAssign<u32> for TtiPoint {
+    fn add_assign(&mut self, rhs: u32) {
+        self.0 = (self.0 + rhs % TTI_PERIOD) % TTI_PERIOD;
+    }
+}